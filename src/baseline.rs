@@ -0,0 +1,137 @@
+use crate::cli::Args;
+use crate::models::SimulationSummary;
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{ self, OpenOptions },
+};
+
+/// Options which don't change the outcome of a simulation, so aren't saved in baselines.
+const UNSAVED_OPTIONS: [&str; 2] = ["save-baseline", "repeat"];
+
+/// The metrics checked by default and their tolerance, as a fraction of the baseline value.
+const DEFAULT_TOLERANCES: [(&str, f64); 4] = [
+    ("total_fees", 0.01),
+    ("total_cost", 0.01),
+    ("failed_count", 0.0),
+    ("max_input_set_size", 0.0),
+];
+
+/// The outcome of a simulation and everything needed to run it again.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Baseline {
+    pub scenario: String,
+    pub backend: String,
+    /// Options the simulation was run with, as given in the command line.
+    pub options: BTreeMap<String, String>,
+    /// Arguments given after `--`, the command of the `external` selector.
+    #[serde(default)]
+    pub trailing: Vec<String>,
+    /// Final value of the summary metrics.
+    pub metrics: BTreeMap<String, f64>,
+    /// The checked metrics and how much each one may grow, as a fraction of its baseline value,
+    /// before being considered a regression. They are all expected to be better when lower.
+    pub tolerances: BTreeMap<String, f64>,
+}
+
+impl Baseline {
+    /// The baseline of the simulation of `scenario` with `backend` and `args`, which ended with
+    /// `summary`.
+    pub fn new(scenario: &str, backend: &str, args: &Args, summary: &SimulationSummary) -> Self {
+        Baseline {
+            scenario: scenario.to_string(),
+            backend: backend.to_string(),
+            options: args
+                .options()
+                .filter(|(name, _)| !UNSAVED_OPTIONS.contains(name))
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            trailing: args.trailing.clone(),
+            metrics: summary
+                .metrics()
+                .into_iter()
+                // Undefined metrics, as the mean of no values, can't be saved as JSON.
+                .filter(|(_, value)| value.is_finite())
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            tolerances: DEFAULT_TOLERANCES
+                .iter()
+                .map(|(name, tolerance)| (name.to_string(), *tolerance))
+                .collect(),
+        }
+    }
+
+    pub fn read(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// The arguments to simulate the baseline again, saving the results in `output_path`.
+    pub fn args(&self, output_path: &str) -> Result<Args, Box<dyn Error>> {
+        let mut args = vec![self.scenario.clone(), output_path.to_string(), self.backend.clone()];
+        for (name, value) in self.options.iter() {
+            args.push(format!("--{}", name));
+            args.push(value.clone());
+        }
+        if !self.trailing.is_empty() {
+            args.push(String::from("--"));
+            args.extend(self.trailing.iter().cloned());
+        }
+
+        Args::parse(args.into_iter())
+    }
+
+    /// Prints how the metrics of `summary` changed from the baseline, returning whether none of
+    /// them grew beyond its tolerance.
+    pub fn check(&self, summary: &SimulationSummary) -> bool {
+        let metrics = summary.metrics();
+        let mut passed = true;
+
+        println!("{:<24} {:>18} {:>18} {:>10} {:>10}  status", "metric", "baseline", "current", "change", "tolerance");
+        for (name, tolerance) in self.tolerances.iter() {
+            let baseline = self.metrics.get(name);
+            let current = metrics.iter().find(|(metric, _)| metric == name).map(|(_, value)| *value);
+
+            let (baseline, current) = match (baseline, current) {
+                (Some(baseline), Some(current)) => (*baseline, current),
+                _ => {
+                    println!("{:<24} unknown metric or missing from the baseline", name);
+                    passed = false;
+                    continue;
+                },
+            };
+
+            let change = if baseline != 0.0 {
+                format!("{:+.2}%", (current - baseline) / baseline.abs() * 100.0)
+            } else { String::from("-") };
+            let status = if current > baseline + tolerance * baseline.abs() {
+                passed = false;
+                "REGRESSED"
+            } else if current < baseline {
+                "improved"
+            } else { "ok" };
+
+            println!(
+                "{:<24} {:>18.2} {:>18.2} {:>10} {:>9.2}%  {}",
+                name,
+                baseline,
+                current,
+                change,
+                tolerance * 100.0,
+                status
+            );
+        }
+
+        passed
+    }
+}
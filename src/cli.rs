@@ -0,0 +1,113 @@
+use crate::selectors::SelectorParams;
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    str::FromStr,
+};
+
+/// Names of the options of the commands, besides the selector parameters.
+const OPTIONS: [&str; 39] = [
+    "ancestor-limit",
+    "ancestor-size-limit",
+    "batch-amount",
+    "batch-count",
+    "batch-flush-feerate",
+    "batch-wait",
+    "blocks",
+    "consolidation",
+    "consolidation-count",
+    "consolidation-feerate",
+    "consolidation-horizon",
+    "consolidation-selection",
+    "defer-feerate",
+    "defer-max-wait",
+    "defer-percentile",
+    "defer-window",
+    "descendant-limit",
+    "descendant-size-limit",
+    "eligibility",
+    "fee-bumping",
+    "mempool",
+    "min-confirmations",
+    "objective",
+    "partial-fulfillment",
+    "partial-fulfillment-rounds",
+    "payment-expiry",
+    "payment-policy",
+    "repeat",
+    "resamples",
+    "save-baseline",
+    "search",
+    "search-seed",
+    "shadow",
+    "space",
+    "sweep",
+    "trials",
+    "uneconomical-feerate",
+    "urgency",
+    "utxo-penalty",
+];
+
+/// Command line arguments split into positionals, `--name value` options and everything after a
+/// bare `--`.
+#[derive(Debug, Default)]
+pub struct Args {
+    positionals: Vec<String>,
+    options: HashMap<String, String>,
+    pub trailing: Vec<String>,
+}
+
+impl Args {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let mut parsed = Args::default();
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                parsed.trailing = args.collect();
+                break;
+            }
+
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    // A mistyped option would otherwise run silently with its default.
+                    if !OPTIONS.contains(&name) && !SelectorParams::NAMES.contains(&name) {
+                        return Err(format!("unknown option --{}", name).into());
+                    }
+                    let value = args.next().ok_or_else(|| format!("expected a value for --{}", name))?;
+                    parsed.options.insert(name.to_string(), value);
+                },
+                None => parsed.positionals.push(arg),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    pub fn positional(&self, index: usize) -> Option<&str> {
+        self.positionals.get(index).map(String::as_str)
+    }
+
+    pub fn required(&self, index: usize, name: &str) -> Result<&str, Box<dyn Error>> {
+        self.positional(index).ok_or_else(|| format!("expected the {} as argument {}", name, index + 1).into())
+    }
+
+    pub fn options(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.options.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    pub fn parsed<T>(&self, name: &str) -> Result<Option<T>, Box<dyn Error>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.option(name)
+            .map(|value| value.parse::<T>().map_err(|err| format!("invalid --{} `{}`: {}", name, value, err).into()))
+            .transpose()
+    }
+}
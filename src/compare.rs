@@ -0,0 +1,133 @@
+use crate::models::TransactionType;
+use crate::stats::{ self, SplitMix64 };
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::File,
+};
+
+use statistical::mean;
+
+/// The fields of a `full_results.csv` row compared between runs.
+#[derive(Debug, serde::Deserialize)]
+struct RecordedAttempt {
+    #[serde(default)]
+    transaction_type: TransactionType,
+    fee: Option<i64>,
+    algorithm: String,
+}
+
+/// What is compared of a simulation run: its final summary and its withdrawals.
+struct Run {
+    path: String,
+    summary: HashMap<String, String>,
+    /// The withdrawals in scenario order, leaving out the consolidations and fee bumps.
+    withdrawals: Vec<RecordedAttempt>,
+}
+
+impl Run {
+    fn read(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut summary_reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(File::open(format!("{}/summary.csv", path))?);
+        let summary: HashMap<String, String> = summary_reader
+            .deserialize()
+            .next()
+            .ok_or_else(|| format!("{}/summary.csv is empty", path))??;
+
+        let mut results_reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(File::open(format!("{}/full_results.csv", path))?);
+        let withdrawals = results_reader
+            .deserialize()
+            .filter(|result: &Result<RecordedAttempt, _>| result.as_ref().map_or(true, |x| x.transaction_type == TransactionType::Payment))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Run {
+            path: path.to_string(),
+            summary,
+            withdrawals,
+        })
+    }
+
+    /// The numeric metrics of the summary, and the failed withdrawals, by name.
+    fn metrics(&self) -> Vec<(String, f64)> {
+        let mut metrics = self.summary
+            .iter()
+            .filter_map(|(name, value)| Some((name.clone(), value.parse::<f64>().ok()?)))
+            .collect::<Vec<(String, f64)>>();
+        metrics.push((
+            String::from("failed_count"),
+            self.withdrawals.iter().filter(|x| x.algorithm == "failed").count() as f64,
+        ));
+        metrics.sort_by(|a, b| a.0.cmp(&b.0));
+        metrics
+    }
+}
+
+/// Prints the differences between the runs saved in `path_a` and `path_b`: of their final
+/// summary metrics, and of the fees paid by both on each withdrawal, with a bootstrap confidence
+/// interval of their mean from `resamples` resamples.
+pub fn compare(path_a: &str, path_b: &str, resamples: usize, seed: u64) -> Result<(), Box<dyn Error>> {
+    let run_a = Run::read(path_a)?;
+    let run_b = Run::read(path_b)?;
+
+    if run_a.summary.get("scenario_file") != run_b.summary.get("scenario_file") {
+        return Err(format!("{} and {} didn't simulate the same scenario", run_a.path, run_b.path).into());
+    }
+
+    println!("a: {}", run_a.path);
+    println!("b: {}", run_b.path);
+    println!();
+    println!("{:<40} {:>18} {:>18} {:>18} {:>10}", "metric", "a", "b", "b - a", "change");
+
+    let metrics_b = run_b.metrics().into_iter().collect::<HashMap<String, f64>>();
+    for (name, a) in run_a.metrics() {
+        let Some(b) = metrics_b.get(&name) else { continue };
+        let change = if a != 0.0 {
+            format!("{:+.2}%", (b - a) / a.abs() * 100.0)
+        } else { String::from("-") };
+        println!("{:<40} {:>18.2} {:>18.2} {:>18.2} {:>10}", name, a, b, b - a, change);
+    }
+
+    // Payments sent in batches, or by different policies, are funded by different withdrawals,
+    // which can't be paired.
+    if run_a.withdrawals.len() != run_b.withdrawals.len() {
+        println!();
+        println!("the runs made a different number of withdrawals, which can't be paired");
+        return Ok(());
+    }
+
+    // Fee differences of the withdrawals both runs funded, as both went through the same ones.
+    let differences = run_a.withdrawals
+        .iter()
+        .zip(run_b.withdrawals.iter())
+        .filter(|(a, b)| a.algorithm != "failed" && b.algorithm != "failed")
+        .filter_map(|(a, b)| Some((b.fee? - a.fee?) as f64))
+        .collect::<Vec<f64>>();
+
+    println!();
+    println!("paired withdrawals: {}", differences.len());
+    if differences.is_empty() {
+        return Ok(());
+    }
+
+    let mean_difference = mean(&differences);
+    let mut rng = SplitMix64::new(seed);
+    match stats::bootstrap_mean_interval(&differences, resamples, &mut rng) {
+        Some((low, high)) => {
+            let significance = if low > 0.0 || high < 0.0 { "significant" } else { "not significant" };
+            println!(
+                "mean fee difference (b - a): {:.2} sats, 95% CI [{:.2}, {:.2}], {}",
+                mean_difference,
+                low,
+                high,
+                significance
+            );
+        },
+        None => println!("mean fee difference (b - a): {:.2} sats", mean_difference),
+    }
+
+    Ok(())
+}
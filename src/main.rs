@@ -1,52 +1,294 @@
+mod baseline;
+mod cli;
+mod compare;
 mod models;
+mod policy;
+mod replay;
 mod selectors;
 mod simulator;
+mod stats;
+mod tuning;
+mod wallet;
 
-use crate::simulator::Simulation;
-use crate::selectors::bdk::BdkCoinSelect;
-use crate::selectors::rust_coinselect::RustCoinSelect;
-use crate::selectors::python::bitcoin_coin_selection::PythonCoinSelect;
-use crate::models::{ PendingPayment, ScenarioEntry, SimulationEntry };
+use crate::baseline::Baseline;
+use crate::cli::Args;
+use crate::policy::{ BatchPolicy, ConsolidationParams, ConsolidationSelection, DeferralPolicy };
+use crate::simulator::{ Consolidator, FeeBumper, ShadowSelectors, Simulation };
+use crate::selectors::pool::CandidatePool;
+use crate::selectors::SelectorParams;
+use crate::tuning::{ ParameterSpace, Search, TuningObjective };
+use crate::wallet::{ BlockModel, ChainLimits, EligibilityFilter, InclusionFeerates, Wallet };
+use crate::models::{ PendingPayment, ScenarioEntry, SimulationEntry, SimulationSummary, Urgency };
 
 use std::{
     env,
     error::Error,
-    ffi::OsString,
     process,
 };
 
+use tracing::{ Level, event };
 use tracing_subscriber::{ EnvFilter, fmt, prelude::* };
 
 const SEGWIT_V1_TXIN_WEIGHT: u32 = 68;
 const SEGWIT_V1_TXOUT_WEIGHT: u32 = 31;
 
-#[derive(Default, Copy, Clone)]
-enum PaymentPolicy {
-    #[default]
-    RollForward,
-    Drop,
-}
+/// The selector parameters given as options, with the `external` selector command after `--`.
+fn selector_params(args: &Args) -> Result<SelectorParams, Box<dyn Error>> {
+    let mut params = SelectorParams {
+        external_command: args.trailing.clone(),
+        ..Default::default()
+    };
 
-fn get_arg(arg_num: usize) -> Result<OsString, Box<dyn Error>> {
-    match env::args_os().nth(arg_num) {
-        None => Err(From::from("expected 1 argument, but got none")),
-        Some(arg) => Ok(arg),
+    for name in SelectorParams::NAMES {
+        if let Some(value) = args.option(name) {
+            params.set(name, value)?;
+        }
     }
+
+    Ok(params)
 }
 
 fn simulate() -> Result<(), Box<dyn Error>> {
-    let input_path = get_arg(1)?.into_string().expect("First argument should be a valid string.");
-    let output_path = get_arg(2)?.into_string().expect("Second argument should be a valid string.");
+    let args = Args::parse(env::args().skip(1))?;
+    let input_path = args.required(0, "scenario file")?;
 
-    let mut selector = PythonCoinSelect::new(10.0, 526, SEGWIT_V1_TXIN_WEIGHT, SEGWIT_V1_TXOUT_WEIGHT)?;
-    let mut simulation = Simulation {
-        payment_policy: PaymentPolicy::Drop,
-        selector: &mut selector
+    if input_path == "backends" {
+        println!("{}", selectors::available_backends().join("\n"));
+        return Ok(());
+    }
+
+    if input_path == "check-external" {
+        let params = selector_params(&args)?;
+        return match selectors::external::conformance::check(&params.external_command, params.worker_timeout)? {
+            true => Ok(()),
+            false => Err(From::from("the external selector doesn't conform to the protocol")),
+        };
+    }
+
+    if input_path == "compare" {
+        return compare::compare(
+            args.required(1, "first run directory")?,
+            args.required(2, "second run directory")?,
+            args.parsed("resamples")?.unwrap_or(10_000),
+            args.parsed("seed")?.unwrap_or_default(),
+        );
+    }
+
+    if input_path == "check-baseline" {
+        let baseline = Baseline::read(args.required(1, "baseline file")?)?;
+        let output_path = args.required(2, "output directory")?;
+        let run_args = baseline.args(output_path)?;
+        let params = selector_params(&run_args)?;
+
+        let summary = run_simulation(&run_args, &baseline.backend, &params, &baseline.scenario, output_path)?;
+        return match baseline.check(&summary) {
+            true => Ok(()),
+            false => Err(From::from("the simulation regressed from the baseline")),
+        };
+    }
+
+    if input_path == "tune" {
+        let scenario_path = args.required(1, "scenario file")?;
+        let output_path = args.required(2, "output directory")?;
+        let backend = args.positional(3).unwrap_or(selectors::default_backend());
+        let space = args
+            .parsed::<ParameterSpace>("space")?
+            .ok_or("expected the parameters to tune in --space")?;
+        let search = Search::from_name(
+            args.option("search").unwrap_or("grid"),
+            args.parsed("trials")?.unwrap_or(20),
+            args.parsed("search-seed")?.unwrap_or_default(),
+        )?;
+        let objective = TuningObjective::new(
+            args.option("objective").unwrap_or("total_cost"),
+            args.parsed("utxo-penalty")?.unwrap_or(0.0),
+        )?;
+        let params = selector_params(&args)?;
+
+        return tuning::tune(&space, search, &objective, &params, output_path, |params, trial_path| {
+            run_simulation(&args, backend, params, scenario_path, trial_path)
+        });
+    }
+
+    if input_path == "sweep" {
+        let scenario_path = args.required(1, "scenario file")?;
+        let output_path = args.required(2, "output directory")?;
+        let backend = args.positional(3).unwrap_or(selectors::default_backend());
+        let space = args
+            .parsed::<ParameterSpace>("sweep")?
+            .ok_or("expected the parameters to sweep in --sweep")?;
+        let params = selector_params(&args)?;
+
+        return tuning::sweep(&space, &params, output_path, |params, setting_path| {
+            run_simulation(&args, backend, params, scenario_path, setting_path)
+        });
+    }
+
+    if input_path == "replay" {
+        let run_path = args.required(1, "run directory")?;
+        let attempt = args.required(2, "attempt id")?.parse::<usize>()?;
+        let backend = args.positional(3).unwrap_or(selectors::default_backend());
+        let params = selector_params(&args)?;
+        let mut selector = selectors::from_name(backend, &params)?;
+        return replay::replay(run_path, attempt, backend, selector.as_mut(), &params.waste_params());
+    }
+
+    let output_path = args.required(1, "output directory")?;
+    let backend = args.positional(2).unwrap_or(selectors::default_backend());
+
+    event!(Level::INFO, "compiled backends: {}", selectors::available_backends().join(", "));
+    let params = selector_params(&args)?;
+
+    let backends = backend.split(',').collect::<Vec<&str>>();
+    if backends.len() == 1 {
+        return simulate_backend(&args, backend, &params, input_path, output_path);
+    }
+
+    if args.option("save-baseline").is_some() {
+        return Err(From::from("--save-baseline takes a single backend"));
+    }
+
+    // Each backend simulates the scenario from scratch in a directory of its own, so they can be
+    // compared afterwards.
+    for backend in backends {
+        simulate_backend(&args, backend, &params, input_path, &format!("{}/{}", output_path, backend))?;
+    }
+
+    Ok(())
+}
+
+/// Simulates the scenario at `input_path` with `backend`, as many times as `--repeat` asks.
+fn simulate_backend(args: &Args, backend: &str, params: &SelectorParams, input_path: &str, output_path: &str) -> Result<(), Box<dyn Error>> {
+    event!(Level::INFO, "simulating backend {}", backend);
+
+    let repeat = match args.parsed::<usize>("repeat")? {
+        Some(0) => return Err(From::from("--repeat needs at least one run")),
+        Some(_) if args.option("save-baseline").is_some() => return Err(From::from("--save-baseline takes a single run, not --repeat")),
+        Some(repeat) => repeat,
+        None => {
+            let summary = run_simulation(args, backend, params, input_path, output_path)?;
+            if let Some(baseline_path) = args.option("save-baseline") {
+                Baseline::new(input_path, backend, args, &summary).save(baseline_path)?;
+            }
+            return Ok(());
+        },
     };
 
-    simulation.run(&input_path, &output_path)
+    // Each repetition is seeded from the given seed onwards, so the whole set can be reproduced.
+    let seed = params.seed.unwrap_or_default();
+    let mut runs = Vec::with_capacity(repeat);
+    for run in 0..repeat {
+        let run_seed = seed.wrapping_add(run as u64);
+        let params = SelectorParams {
+            seed: Some(run_seed),
+            ..params.clone()
+        };
+        event!(Level::INFO, "repetition {}/{} with seed {}", run + 1, repeat, run_seed);
+
+        let summary = run_simulation(args, backend, &params, input_path, &format!("{}/run_{}", output_path, run))?;
+        runs.push((run_seed, summary));
+    }
+
+    stats::write_report(output_path, &runs)
 }
 
+/// Simulates the scenario at `input_path` with `backend` and the shadow selectors and
+/// consolidation policy given in `args`, saving the results in `output_path`.
+fn run_simulation(args: &Args, backend: &str, params: &SelectorParams, input_path: &str, output_path: &str) -> Result<SimulationSummary, Box<dyn Error>> {
+    let mut selector = selectors::from_name(backend, params)?;
+
+    let shadow = match args.option("shadow") {
+        Some(names) => Some(ShadowSelectors {
+            primary: backend.to_string(),
+            selectors: names
+                .split(',')
+                .map(|name| Ok((name.to_string(), selectors::from_name(name, params)?)))
+                .collect::<Result<Vec<_>, Box<dyn Error>>>()?,
+            waste_params: params.waste_params(),
+            objective: params.optimal_objective,
+        }),
+        None => None,
+    };
+
+    let consolidation = match args.option("consolidation") {
+        Some(name) => Some(Consolidator {
+            policy: policy::consolidation_from_name(name, ConsolidationParams {
+                horizon: args.parsed("consolidation-horizon")?.unwrap_or(1000),
+                max_feerate: args.parsed("consolidation-feerate")?.unwrap_or(params.long_term_feerate as f64),
+                count: args.parsed("consolidation-count")?.unwrap_or(10),
+                selection: args.parsed("consolidation-selection")?.unwrap_or(ConsolidationSelection::Smallest),
+                uneconomical_feerate: args.parsed("uneconomical-feerate")?.unwrap_or(params.long_term_feerate as f64),
+                input_drain_weight: params.input_drain_weight,
+                output_drain_weight: params.output_drain_weight,
+            })?,
+            pool: CandidatePool::new(params.long_term_feerate, params.dust_limit, params.input_drain_weight, params.output_drain_weight),
+        }),
+        None => None,
+    };
+
+    let batch = BatchPolicy {
+        max_count: args.parsed("batch-count")?,
+        max_amount: args.parsed("batch-amount")?,
+        max_wait: args.parsed("batch-wait")?,
+        flush_feerate: args.parsed("batch-flush-feerate")?,
+    };
+
+    let deferral = match args.option("defer-feerate").is_some() || args.option("defer-percentile").is_some() {
+        true => Some(DeferralPolicy::new(
+            args.parsed("defer-feerate")?,
+            args.parsed("defer-percentile")?,
+            args.parsed("defer-window")?.unwrap_or(144),
+            args.parsed("defer-max-wait")?,
+            args.parsed("urgency")?.unwrap_or(Urgency::Normal),
+        )?),
+        false => None,
+    };
+
+    let defaults = ChainLimits::default();
+    let limits = ChainLimits {
+        ancestor_count: args.parsed("ancestor-limit")?.unwrap_or(defaults.ancestor_count),
+        descendant_count: args.parsed("descendant-limit")?.unwrap_or(defaults.descendant_count),
+        ancestor_size: args.parsed::<u64>("ancestor-size-limit")?.map_or(defaults.ancestor_size, |x| x * 1000),
+        descendant_size: args.parsed::<u64>("descendant-size-limit")?.map_or(defaults.descendant_size, |x| x * 1000),
+    };
+    let tiers = match (args.option("eligibility"), args.parsed("min-confirmations")?) {
+        (None, confirmations) => vec![EligibilityFilter::confirmations(confirmations.unwrap_or(1))],
+        (Some("core"), None) => EligibilityFilter::core_tiers(limits.ancestor_count),
+        (Some("core"), Some(_)) => return Err(From::from("--min-confirmations can't be given with the core eligibility tiers")),
+        (Some(name), _) => return Err(format!("unknown eligibility `{}`, expected core", name).into()),
+    };
+    let inclusion = args.option("mempool").map(InclusionFeerates::from_arg).transpose()?;
+    let fee_bumping = match args.parsed("fee-bumping")? {
+        Some(_) if inclusion.is_none() => return Err(From::from("--fee-bumping needs --mempool to leave transactions unconfirmed")),
+        Some(strategy) => Some(FeeBumper {
+            strategy,
+            dust_limit: params.dust_limit,
+        }),
+        None => None,
+    };
+    let wallet = match args.parsed::<BlockModel>("blocks")? {
+        Some(blocks) => Some(Wallet::new(blocks, tiers, limits, inclusion, params.long_term_feerate)),
+        None if args.option("min-confirmations").is_some() || args.option("eligibility").is_some() || inclusion.is_some() => {
+            return Err(From::from("confirmations need --blocks to map the scenario rows to blocks"));
+        },
+        None => None,
+    };
+
+    let mut simulation = Simulation {
+        payment_policy: policy::payment_from_name(args.option("payment-policy").unwrap_or("drop"), batch)?,
+        payment_expiry: args.parsed("payment-expiry")?,
+        partial_fulfillment: args.parsed("partial-fulfillment")?,
+        partial_fulfillment_rounds: args.parsed("partial-fulfillment-rounds")?.unwrap_or(100_000),
+        deferral,
+        selector: selector.as_mut(),
+        shadow,
+        consolidation,
+        wallet,
+        fee_bumping,
+    };
+
+    simulation.run(input_path, output_path)
+}
 
 fn main() {
     tracing_subscriber::registry()
@@ -2,31 +2,107 @@ use std::{
     cmp,
     error::Error,
     collections::HashMap,
+    str::FromStr,
 };
 use serde::ser::{ Serialize, Serializer, SerializeStruct };
 use statistical::{ mean, standard_deviation };
 
-#[derive(Debug, serde::Deserialize, Clone)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 pub struct ScenarioEntry {
     pub amount: f64,
     pub fee_rate_per_kvb: f32,
+    /// How soon a withdrawal must be paid, from an optional third column.
+    #[serde(default, skip_serializing)]
+    pub urgency: Option<Urgency>,
+    /// Height of the block the row happens in, from an optional fourth column.
+    #[serde(default, skip_serializing)]
+    pub block: Option<u64>,
 }
 
-#[derive(Clone)]
+/// How soon a payment must be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    /// Sent as soon as the payment policy allows.
+    Urgent,
+    /// May be deferred until the fees are low.
+    Normal,
+}
+
+impl FromStr for Urgency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "urgent" => Ok(Urgency::Urgent),
+            "normal" => Ok(Urgency::Normal),
+            _ => Err(format!("unknown urgency `{}`, expected urgent or normal", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize)]
 pub struct PendingPayment {
     pub amount: u64,
     pub weight: u32,
+    /// Scenario row at which the payment arrived.
+    #[serde(skip)]
+    pub arrival: usize,
+    /// Withdrawals which tried to fund the payment.
+    #[serde(skip)]
+    pub attempts: usize,
+    /// The urgency given by the scenario, if any.
+    #[serde(skip)]
+    pub urgency: Option<Urgency>,
+}
+
+/// What a transaction of the simulation was made for.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionType {
+    #[default]
+    Payment,
+    /// Merges wallet UTXOs into a single one, made by a consolidation policy.
+    Consolidation,
+    /// Raises the feerate of a transaction which missed a block, by replacing it or spending its
+    /// change.
+    #[serde(rename = "fee_bump")]
+    FeeBump,
+}
+
+/// Why a withdrawal couldn't be funded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    /// The selector found no selection of the UTxOs it was given.
+    Selection,
+    /// The selection needed unconfirmed UTxOs whose chains are too long for the mempool policy.
+    ChainLimits,
 }
 
 #[derive(Debug, Default, Clone, serde::Deserialize)]
 pub struct SimulationEntry {
     pub id: usize,
+    #[serde(default)]
+    pub transaction_type: TransactionType,
     pub inputs: Vec<u64>,
     pub amount: u64,
+    /// The amount of each payment, in the order they were given to the selector.
+    #[serde(default)]
+    pub payment_amounts: Vec<u64>,
     pub fee: Option<i64>,
     pub target_feerate: f32,
+    /// The feerate of the scenario row, in BTC per kvB, as given to the selector.
+    #[serde(default)]
+    pub fee_rate_per_kvb: f32,
     pub real_feerate: Option<f32>,
     pub algorithm: String,
+    /// The eligibility filter of the UTxOs given to the selector, when confirmations are
+    /// simulated.
+    #[serde(default)]
+    pub eligibility_filter: Option<String>,
+    #[serde(default)]
+    pub failure_reason: Option<FailureReason>,
     pub negative_effective_valued_inputs: Option<usize>,
     pub output_count: Option<usize>,
     pub change_amount: Option<u64>,
@@ -46,13 +122,24 @@ impl Serialize for SimulationEntry {
             Some(self.inputs.len())
         } else { None };
 
-        let mut state = serializer.serialize_struct("SimulationEntry", 13)?;
+        let payment_amounts = self.payment_amounts
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<String>>()
+            .join(",");
+
+        let mut state = serializer.serialize_struct("SimulationEntry", 18)?;
         state.serialize_field("id", &self.id)?;
+        state.serialize_field("transaction_type", &self.transaction_type)?;
         state.serialize_field("amount", &self.amount)?;
+        state.serialize_field("payment_amounts", &payment_amounts)?;
         state.serialize_field("fee", &self.fee)?;
         state.serialize_field("target_feerate", &self.target_feerate)?;
+        state.serialize_field("fee_rate_per_kvb", &self.fee_rate_per_kvb)?;
         state.serialize_field("real_feerate", &self.real_feerate)?;
         state.serialize_field("algorithm", &self.algorithm)?;
+        state.serialize_field("eligibility_filter", &self.eligibility_filter)?;
+        state.serialize_field("failure_reason", &self.failure_reason)?;
         state.serialize_field("input_count", &input_count)?;
         state.serialize_field("negative_effective_valued_inputs", &self.negative_effective_valued_inputs)?;
         state.serialize_field("output_count", &self.output_count)?;
@@ -71,22 +158,37 @@ pub struct SimulationSummary {
     pub current_balance: u64,
     pub current_utxo_set_count: usize,
     pub deposit_count: usize,
-    pub inputs_spent_count: usize,
     pub withdraw_count: usize,
     pub negative_effective_valued_inputs_count: usize,
     pub created_change_outputs_count: usize,
     pub changeless_transaction_count: usize,
     pub min_change_value: u64,
     pub max_change_value: u64,
+    /// Fees paid by every wallet transaction, consolidations and fee bumps included.
     pub total_fees: f32,
-    pub mean_fees_per_withdraw: f32,
+    /// Fees paid by the withdrawals alone.
+    pub payment_fees: f32,
     pub cost_to_empty_at_long_term_feerate: f32,
-    pub total_cost: f32,
     pub min_input_set_size: usize,
     pub max_input_set_size: usize,
     pub change_values: Vec<f32>,
     pub input_set_sizes: Vec<f32>,
-    pub usage: String,
+    /// Payments queued when each scenario row was reached.
+    pub queue_lengths: Vec<f32>,
+    /// Scenario rows each paid payment waited since it arrived.
+    pub payment_waits: Vec<f32>,
+    /// Payments given up after failing to be funded `--payment-expiry` times.
+    pub expired_payment_count: usize,
+    /// Payments given up by the `drop` policy after failing to be funded once.
+    pub dropped_payment_count: usize,
+    /// Withdrawals which failed because of the mempool chain limits.
+    pub chain_limit_failure_count: usize,
+    pub consolidation_count: usize,
+    pub consolidated_utxo_count: usize,
+    pub consolidation_fees: f32,
+    pub fee_bump_count: usize,
+    /// Fees paid by the fee bumps on top of the ones of the bumped transactions.
+    pub fee_bump_fees: f32,
 }
 
 impl Default for SimulationSummary {
@@ -97,7 +199,6 @@ impl Default for SimulationSummary {
             current_balance: u64::default(),
             current_utxo_set_count: usize::default(),
             deposit_count: usize::default(),
-            inputs_spent_count: usize::default(),
             withdraw_count: usize::default(),
             negative_effective_valued_inputs_count: usize::default(),
             created_change_outputs_count: usize::default(),
@@ -105,45 +206,70 @@ impl Default for SimulationSummary {
             min_change_value: u64::MAX,
             max_change_value: u64::MIN,
             total_fees: f32::default(),
-            mean_fees_per_withdraw: f32::default(),
+            payment_fees: f32::default(),
             cost_to_empty_at_long_term_feerate: f32::default(),
-            total_cost: f32::default(),
             min_input_set_size: usize::MAX,
             max_input_set_size: usize::MIN,
             change_values: <Vec<f32>>::default(),
             input_set_sizes: <Vec<f32>>::default(),
-            usage: String::default(),
+            queue_lengths: <Vec<f32>>::default(),
+            payment_waits: <Vec<f32>>::default(),
+            expired_payment_count: usize::default(),
+            dropped_payment_count: usize::default(),
+            chain_limit_failure_count: usize::default(),
+            consolidation_count: usize::default(),
+            consolidated_utxo_count: usize::default(),
+            consolidation_fees: f32::default(),
+            fee_bump_count: usize::default(),
+            fee_bump_fees: f32::default(),
         }
     }
 }
 
 impl SimulationSummary {
     pub fn update(&mut self, simulation_entry: &SimulationEntry) -> Result<(), Box<dyn Error>> {
-        if simulation_entry.algorithm != "failed" {
-            self.withdraw_count += 1;
-        }
-
-        self.algorithm_frequencies.entry(simulation_entry.algorithm.clone()).and_modify(|e| *e += 1).or_insert(1);
-
         self.current_balance = simulation_entry.balance;
         self.current_utxo_set_count = simulation_entry.utxo_count_after_payment;
         self.cost_to_empty_at_long_term_feerate = simulation_entry.cost_to_empty_at_long_term_feerate;
 
-        self.negative_effective_valued_inputs_count += if let Some(count) = simulation_entry.negative_effective_valued_inputs {
-            count
-        } else { 0 };
+        self.negative_effective_valued_inputs_count += simulation_entry.negative_effective_valued_inputs.unwrap_or_default();
 
         self.total_fees += if let Some(fee) = simulation_entry.fee {
             fee as f32
         } else { 0.0 };
 
-        if let Some(change_value) = simulation_entry.change_amount {
-            self.change_values.push(change_value as f32);
-            self.created_change_outputs_count += 1;
-            self.min_change_value = cmp::min(self.min_change_value, change_value);
-            self.max_change_value = cmp::max(self.max_change_value, change_value);
-        } else {
-            self.changeless_transaction_count += 1;
+        // Consolidations pay fees, but neither fund payments nor create change, and are
+        // accounted apart from the withdrawals.
+        if simulation_entry.transaction_type == TransactionType::Consolidation {
+            self.consolidation_count += 1;
+            self.consolidated_utxo_count += simulation_entry.inputs.len();
+            self.consolidation_fees += simulation_entry.fee.unwrap_or_default() as f32;
+            return Ok(());
+        }
+        if simulation_entry.transaction_type == TransactionType::FeeBump {
+            self.fee_bump_count += 1;
+            self.fee_bump_fees += simulation_entry.fee.unwrap_or_default() as f32;
+            return Ok(());
+        }
+
+        if simulation_entry.algorithm != "failed" {
+            self.withdraw_count += 1;
+        }
+        self.payment_fees += simulation_entry.fee.unwrap_or_default() as f32;
+        if simulation_entry.failure_reason == Some(FailureReason::ChainLimits) {
+            self.chain_limit_failure_count += 1;
+        }
+
+        self.algorithm_frequencies.entry(simulation_entry.algorithm.clone()).and_modify(|e| *e += 1).or_insert(1);
+
+        match simulation_entry.change_amount {
+            Some(change_value) => {
+                self.change_values.push(change_value as f32);
+                self.created_change_outputs_count += 1;
+                self.min_change_value = cmp::min(self.min_change_value, change_value);
+                self.max_change_value = cmp::max(self.max_change_value, change_value);
+            },
+            None => self.changeless_transaction_count += 1,
         };
 
         if !simulation_entry.inputs.is_empty() {
@@ -156,6 +282,56 @@ impl SimulationSummary {
     }
 }
 
+/// The value under which lie `p` of the `values`, by nearest rank, or `NaN` if there are none.
+fn percentile(values: &[f32], p: f64) -> f32 {
+    if values.is_empty() {
+        return f32::NAN;
+    }
+
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl SimulationSummary {
+    /// The numeric metrics of the summary at the end of a simulation, by name.
+    pub fn metrics(&self) -> Vec<(&'static str, f64)> {
+        vec![
+            ("current_balance", self.current_balance as f64),
+            ("current_utxo_set_count", self.current_utxo_set_count as f64),
+            ("deposit_count", self.deposit_count as f64),
+            ("inputs_spent_count", self.input_set_sizes.iter().sum::<f32>() as f64),
+            ("withdraw_count", self.withdraw_count as f64),
+            ("negative_effective_valued_inputs_count", self.negative_effective_valued_inputs_count as f64),
+            ("created_change_outputs_count", self.created_change_outputs_count as f64),
+            ("changeless_transaction_count", self.changeless_transaction_count as f64),
+            ("mean_change_value", mean(&self.change_values) as f64),
+            ("total_fees", self.total_fees as f64),
+            ("payment_fees", self.payment_fees as f64),
+            ("mean_fees_per_withdraw", (self.payment_fees / self.withdraw_count as f32) as f64),
+            ("cost_to_empty_at_long_term_fee_rate", self.cost_to_empty_at_long_term_feerate as f64),
+            ("total_cost", (self.total_fees + self.cost_to_empty_at_long_term_feerate) as f64),
+            ("mean_input_set_size", mean(&self.input_set_sizes) as f64),
+            ("max_input_set_size", self.max_input_set_size as f64),
+            ("failed_count", self.algorithm_frequencies.get("failed").copied().unwrap_or_default() as f64),
+            ("chain_limit_failure_count", self.chain_limit_failure_count as f64),
+            ("mean_queue_length", mean(&self.queue_lengths) as f64),
+            ("max_queue_length", percentile(&self.queue_lengths, 1.0) as f64),
+            ("expired_payment_count", self.expired_payment_count as f64),
+            ("dropped_payment_count", self.dropped_payment_count as f64),
+            ("mean_payment_wait", mean(&self.payment_waits) as f64),
+            ("p90_payment_wait", percentile(&self.payment_waits, 0.9) as f64),
+            ("p99_payment_wait", percentile(&self.payment_waits, 0.99) as f64),
+            ("consolidation_count", self.consolidation_count as f64),
+            ("consolidated_utxo_count", self.consolidated_utxo_count as f64),
+            ("consolidation_fees", self.consolidation_fees as f64),
+            ("fee_bump_count", self.fee_bump_count as f64),
+            ("fee_bump_fees", self.fee_bump_fees as f64),
+        ]
+    }
+}
+
 impl Serialize for SimulationSummary {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -171,7 +347,7 @@ impl Serialize for SimulationSummary {
 
         let usage = self.algorithm_frequencies.iter().map(|(key, value)| format!("{}: {}", key, value)).collect::<Vec<_>>().join(","); 
 
-        let mut state = serializer.serialize_struct("SimulationSummary", 22)?;
+        let mut state = serializer.serialize_struct("SimulationSummary", 39)?;
         state.serialize_field("scenario_file", &self.scenario_file)?;
         state.serialize_field("current_balance", &self.current_balance)?;
         state.serialize_field("current_utxo_set_count", &self.current_utxo_set_count)?;
@@ -186,13 +362,31 @@ impl Serialize for SimulationSummary {
         state.serialize_field("mean_change_value", &(mean(&self.change_values)))?;
         state.serialize_field("std_dev_of_change_value", &std_dev_of_change_value)?;
         state.serialize_field("total_fees", &self.total_fees)?;
-        state.serialize_field("mean_fees_per_withdraw", &(self.total_fees / self.withdraw_count as f32))?;
+        state.serialize_field("payment_fees", &self.payment_fees)?;
+        state.serialize_field("mean_fees_per_withdraw", &(self.payment_fees / self.withdraw_count as f32))?;
         state.serialize_field("cost_to_empty_at_long_term_fee_rate", &self.cost_to_empty_at_long_term_feerate)?;
         state.serialize_field("total_cost", &(self.total_fees + self.cost_to_empty_at_long_term_feerate))?;
         state.serialize_field("min_input_set_size", &self.min_input_set_size)?;
         state.serialize_field("max_input_set_size", &self.max_input_set_size)?;
         state.serialize_field("mean_input_set_size", &(mean(&self.input_set_sizes)))?;
         state.serialize_field("std_dev_of_input_set_size", &std_dev_of_input_set_size)?;
+        state.serialize_field("chain_limit_failure_count", &self.chain_limit_failure_count)?;
+        state.serialize_field("queue_length", &(self.queue_lengths.last().copied().unwrap_or_default() as usize))?;
+        state.serialize_field("mean_queue_length", &mean(&self.queue_lengths))?;
+        state.serialize_field("max_queue_length", &(percentile(&self.queue_lengths, 1.0) as usize))?;
+        state.serialize_field("paid_payment_count", &self.payment_waits.len())?;
+        state.serialize_field("expired_payment_count", &self.expired_payment_count)?;
+        state.serialize_field("dropped_payment_count", &self.dropped_payment_count)?;
+        state.serialize_field("mean_payment_wait", &mean(&self.payment_waits))?;
+        state.serialize_field("p50_payment_wait", &percentile(&self.payment_waits, 0.5))?;
+        state.serialize_field("p90_payment_wait", &percentile(&self.payment_waits, 0.9))?;
+        state.serialize_field("p99_payment_wait", &percentile(&self.payment_waits, 0.99))?;
+        state.serialize_field("max_payment_wait", &percentile(&self.payment_waits, 1.0))?;
+        state.serialize_field("consolidation_count", &self.consolidation_count)?;
+        state.serialize_field("consolidated_utxo_count", &self.consolidated_utxo_count)?;
+        state.serialize_field("consolidation_fees", &self.consolidation_fees)?;
+        state.serialize_field("fee_bump_count", &self.fee_bump_count)?;
+        state.serialize_field("fee_bump_fees", &self.fee_bump_fees)?;
         state.serialize_field("usage", &usage)?;
         state.end()
     }
@@ -0,0 +1,638 @@
+use crate::SEGWIT_V1_TXIN_WEIGHT;
+use crate::models::{ PendingPayment, ScenarioEntry, Urgency };
+use crate::selectors::pool;
+
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    error::Error,
+    mem,
+    str::FromStr,
+};
+
+use tracing::{ Level, event };
+
+/// When the queued payments are sent, and what happens to them when they can't be funded.
+#[derive(Debug, Default, Copy, Clone)]
+pub enum PaymentPolicy {
+    /// Each payment is sent as it arrives, along with the ones which failed before.
+    #[default]
+    RollForward,
+    /// Each payment is sent as it arrives, and given up if it can't be funded.
+    Drop,
+    /// Payments are held until the batch is due, and kept queued while they can't be funded.
+    Batch(BatchPolicy),
+}
+
+impl PaymentPolicy {
+    /// Whether the queued `payments` are to be sent at a row with `fee_rate_per_kvb`, where a
+    /// new payment `arrived` or not, the oldest of them having waited `waited` rows.
+    pub fn is_due(&self, payments: &[PendingPayment], arrived: bool, waited: usize, fee_rate_per_kvb: f32) -> bool {
+        match self {
+            PaymentPolicy::Batch(batch) => batch.is_due(payments, waited, fee_rate_per_kvb),
+            _ => arrived,
+        }
+    }
+
+    /// Whether the payments which couldn't be funded stay queued to be sent again.
+    pub fn retries(&self) -> bool {
+        !matches!(self, PaymentPolicy::Drop)
+    }
+}
+
+/// Conditions to send the held payments together, any of them is enough.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BatchPolicy {
+    /// Payments held.
+    pub max_count: Option<usize>,
+    /// Amount held, in sats.
+    pub max_amount: Option<u64>,
+    /// Scenario rows the oldest payment has been held.
+    pub max_wait: Option<usize>,
+    /// Feerate, in sat/vB, at or under which the held payments are sent early.
+    pub flush_feerate: Option<f64>,
+}
+
+impl BatchPolicy {
+    fn is_due(&self, payments: &[PendingPayment], waited: usize, fee_rate_per_kvb: f32) -> bool {
+        self.max_count.is_some_and(|x| payments.len() >= x)
+            || self.max_amount.is_some_and(|x| payments.iter().map(|payment| payment.amount).sum::<u64>() >= x)
+            || self.max_wait.is_some_and(|x| waited >= x)
+            || self.flush_feerate.is_some_and(|x| pool::sat_per_vb(fee_rate_per_kvb) <= x)
+    }
+}
+
+pub fn payment_from_name(name: &str, batch: BatchPolicy) -> Result<PaymentPolicy, Box<dyn Error>> {
+    match name {
+        "roll-forward" => Ok(PaymentPolicy::RollForward),
+        "drop" => Ok(PaymentPolicy::Drop),
+        // Without a count, amount or wait limit the payments could be held forever.
+        "batch" if batch.max_count.is_none() && batch.max_amount.is_none() && batch.max_wait.is_none() => {
+            Err(From::from("the batch policy needs --batch-count, --batch-amount or --batch-wait"))
+        },
+        "batch" => Ok(PaymentPolicy::Batch(batch)),
+        _ => Err(format!("unknown payment policy `{}`, expected roll-forward, drop or batch", name).into()),
+    }
+}
+
+/// Holds the payments which aren't urgent while the feerate is high, to send them in the next
+/// cheap window.
+#[derive(Debug)]
+pub struct DeferralPolicy {
+    /// Feerate, in sat/vB, above which payments are deferred.
+    pub max_feerate: Option<f64>,
+    /// Percentile, from 0 to 100, of the feerates of the last `window` rows above which payments
+    /// are deferred.
+    pub percentile: Option<f64>,
+    pub window: usize,
+    /// Scenario rows after which a deferred payment is sent whatever the feerate.
+    pub max_wait: Option<usize>,
+    /// Urgency of the payments the scenario gives none.
+    pub default_urgency: Urgency,
+    recent_fee_rates: VecDeque<f64>,
+}
+
+impl DeferralPolicy {
+    pub fn new(max_feerate: Option<f64>, percentile: Option<f64>, window: usize, max_wait: Option<usize>, default_urgency: Urgency) -> Result<Self, Box<dyn Error>> {
+        if max_feerate.is_none() && percentile.is_none() {
+            return Err(From::from("deferring payments needs --defer-feerate or --defer-percentile"));
+        }
+        if percentile.is_some_and(|x| !(0.0..=100.0).contains(&x)) || window == 0 {
+            return Err(From::from("--defer-percentile must be between 0 and 100 over a window of at least one row"));
+        }
+
+        Ok(DeferralPolicy {
+            max_feerate,
+            percentile,
+            window,
+            max_wait,
+            default_urgency,
+            recent_fee_rates: VecDeque::with_capacity(window),
+        })
+    }
+
+    /// Records the feerate of a new scenario row, returning whether it's cheap enough to send the
+    /// deferred payments.
+    pub fn observe(&mut self, fee_rate_per_kvb: f32) -> bool {
+        let fee_rate = pool::sat_per_vb(fee_rate_per_kvb);
+        let over_max = self.max_feerate.is_some_and(|x| fee_rate > x);
+        // Compared with the rows before this one, by nearest rank.
+        let over_percentile = match self.percentile {
+            Some(percentile) if !self.recent_fee_rates.is_empty() => {
+                let mut sorted = self.recent_fee_rates.iter().copied().collect::<Vec<f64>>();
+                sorted.sort_by(f64::total_cmp);
+                let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+                fee_rate > sorted[rank.clamp(1, sorted.len()) - 1]
+            },
+            _ => false,
+        };
+
+        if self.recent_fee_rates.len() == self.window {
+            self.recent_fee_rates.pop_front();
+        }
+        self.recent_fee_rates.push_back(fee_rate);
+
+        !over_max && !over_percentile
+    }
+
+    /// Whether `payment` is held at the scenario row `position`, depending on it being `cheap`.
+    pub fn defers(&self, payment: &PendingPayment, position: usize, cheap: bool) -> bool {
+        payment.urgency.unwrap_or(self.default_urgency) == Urgency::Normal
+            && !cheap
+            && self.max_wait.is_none_or(|x| position - payment.arrival < x)
+    }
+}
+
+/// Which of the queued payments are sent when the wallet can't fund all of them.
+#[derive(Debug, Clone, Copy)]
+pub enum PartialFulfillment {
+    /// In arrival order, skipping the ones which don't fit in the funds left.
+    OldestFirst,
+    /// Smallest amounts first, skipping the ones which don't fit in the funds left, to pay as
+    /// many payments as possible.
+    SmallestFirst,
+    /// The payments of the highest total amount the funds can pay, searched within a budget of
+    /// rounds. Past it, the best of the greedy oldest first and smallest first splits.
+    MaxValue,
+}
+
+impl FromStr for PartialFulfillment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oldest-first" => Ok(PartialFulfillment::OldestFirst),
+            "smallest-first" => Ok(PartialFulfillment::SmallestFirst),
+            "max-value" => Ok(PartialFulfillment::MaxValue),
+            _ => Err(format!("unknown partial fulfillment `{}`, expected oldest-first, smallest-first or max-value", s)),
+        }
+    }
+}
+
+impl PartialFulfillment {
+    /// Takes out of the `queue` the payments to send at `fee_rate_per_kvb` from the wallet
+    /// `utxos`: all of them if they can be funded, or else the ones picked by the strategy, in
+    /// arrival order. The whole queue is sent when none of them can be funded. The max value
+    /// search stops after `rounds`.
+    pub fn split(&self, queue: &mut Vec<PendingPayment>, utxos: &[u64], fee_rate_per_kvb: f32, rounds: usize) -> Vec<PendingPayment> {
+        let funds = pool::funds(utxos, fee_rate_per_kvb);
+        let costs = queue.iter().map(|payment| pool::payment_cost(payment, fee_rate_per_kvb)).collect::<Vec<u64>>();
+        if costs.iter().sum::<u64>() <= funds {
+            return mem::take(queue);
+        }
+
+        let amounts = queue.iter().map(|payment| payment.amount).collect::<Vec<u64>>();
+        let oldest_first = || greedy(&costs, (0..queue.len()).collect(), funds);
+        let smallest_first = || {
+            let mut order = (0..queue.len()).collect::<Vec<usize>>();
+            order.sort_by_key(|index| amounts[*index]);
+            greedy(&costs, order, funds)
+        };
+        let picked = match self {
+            PartialFulfillment::OldestFirst => oldest_first(),
+            PartialFulfillment::SmallestFirst => smallest_first(),
+            PartialFulfillment::MaxValue => match most_valuable(&costs, &amounts, funds, rounds) {
+                Ok(picked) => picked,
+                Err(searched) => {
+                    event!(Level::DEBUG, "max value split of {} payments ran out of {} rounds", queue.len(), rounds);
+                    let value = |picked: &Vec<bool>| amounts.iter().zip(picked).filter(|(_, x)| **x).map(|(amount, _)| amount).sum::<u64>();
+                    [searched, oldest_first(), smallest_first()].into_iter().max_by_key(value).unwrap()
+                },
+            },
+        };
+        if !picked.contains(&true) {
+            return mem::take(queue);
+        }
+
+        let (batch, queued) = mem::take(queue)
+            .into_iter()
+            .zip(picked)
+            .partition::<Vec<_>, _>(|(_, picked)| *picked);
+        *queue = queued.into_iter().map(|(payment, _)| payment).collect();
+        batch.into_iter().map(|(payment, _)| payment).collect()
+    }
+}
+
+/// Picks the payments costing `costs` in the given `order`, skipping the ones which don't fit in
+/// the `funds` left.
+fn greedy(costs: &[u64], order: Vec<usize>, funds: u64) -> Vec<bool> {
+    let mut left = funds;
+    let mut picked = vec![false; costs.len()];
+    for index in order {
+        if let Some(rest) = left.checked_sub(costs[index]) {
+            left = rest;
+            picked[index] = true;
+        }
+    }
+    picked
+}
+
+/// The payments of the highest total amount fitting in the funds being searched, as a 0/1
+/// knapsack.
+struct PaymentSearch<'a> {
+    costs: &'a [u64],
+    amounts: &'a [u64],
+    /// Indices of the payments, by decreasing amount.
+    order: Vec<usize>,
+    /// Amount of the payments from each position of `order` to the end.
+    remaining_amount: Vec<u64>,
+    picked: Vec<bool>,
+    best: (u64, Vec<bool>),
+    /// Rounds left before the search gives up.
+    rounds: usize,
+}
+
+impl PaymentSearch<'_> {
+    /// Explores every pick extending the current one, worth `amount` and leaving `left` of the
+    /// funds, with payments from `position` on.
+    fn explore(&mut self, position: usize, left: u64, amount: u64) {
+        if self.rounds == 0 {
+            return;
+        }
+        self.rounds -= 1;
+
+        if amount > self.best.0 {
+            self.best = (amount, self.picked.clone());
+        }
+        if position == self.order.len() || amount + self.remaining_amount[position] <= self.best.0 {
+            return;
+        }
+
+        let index = self.order[position];
+        if let Some(rest) = left.checked_sub(self.costs[index]) {
+            self.picked[index] = true;
+            self.explore(position + 1, rest, amount + self.amounts[index]);
+            self.picked[index] = false;
+        }
+        self.explore(position + 1, left, amount);
+    }
+}
+
+/// Picks the payments of the highest total of `amounts` whose `costs` fit in the `funds`,
+/// searching every pick but those which can't beat the best one even adding all the payments
+/// left. Gives the best pick found so far as an error when the search takes over `rounds`.
+fn most_valuable(costs: &[u64], amounts: &[u64], funds: u64, rounds: usize) -> Result<Vec<bool>, Vec<bool>> {
+    let mut order = (0..costs.len()).collect::<Vec<usize>>();
+    order.sort_by_key(|index| Reverse(amounts[*index]));
+    let mut remaining_amount = vec![0; order.len() + 1];
+    for position in (0..order.len()).rev() {
+        remaining_amount[position] = remaining_amount[position + 1] + amounts[order[position]];
+    }
+
+    let mut search = PaymentSearch {
+        costs,
+        amounts,
+        order,
+        remaining_amount,
+        picked: vec![false; costs.len()],
+        best: (0, vec![false; costs.len()]),
+        rounds,
+    };
+    search.explore(0, funds, 0);
+    match search.rounds {
+        0 => Err(search.best.1),
+        _ => Ok(search.best.1),
+    }
+}
+
+/// Decides when to merge the wallet UTXOs into a single one, outside of the payments.
+pub trait ConsolidationPolicy {
+    /// Indices of the `utxos` to consolidate before processing the scenario row at `position`,
+    /// if any. The whole `scenario` is given, but only clairvoyant policies should look ahead of
+    /// `position`.
+    fn consolidate(&mut self, scenario: &[ScenarioEntry], position: usize, utxos: &[u64]) -> Option<Vec<usize>>;
+    /// Recorded as the `algorithm` of the consolidation transactions.
+    fn name(&self) -> &'static str;
+}
+
+/// Settings of the consolidation policies, each one uses those it understands.
+#[derive(Debug, Clone, Copy)]
+pub struct ConsolidationParams {
+    /// Scenario rows the clairvoyant policy looks ahead.
+    pub horizon: usize,
+    /// Feerate, in sat/vB, at or under which the opportunistic policy consolidates.
+    pub max_feerate: f64,
+    /// UTXOs merged by each opportunistic consolidation.
+    pub count: usize,
+    pub selection: ConsolidationSelection,
+    /// Feerate, in sat/vB, at which spending a UTXO costs more than it's worth for it to be
+    /// uneconomical.
+    pub uneconomical_feerate: f64,
+    /// Size of the input spending the consolidation output.
+    pub input_drain_weight: u32,
+    /// Size of the consolidation output.
+    pub output_drain_weight: u32,
+}
+
+/// Which UTXOs the opportunistic policy merges.
+#[derive(Debug, Clone, Copy)]
+pub enum ConsolidationSelection {
+    /// The smallest ones.
+    Smallest,
+    /// The smallest ones among those which are uneconomical.
+    Uneconomical,
+}
+
+impl FromStr for ConsolidationSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "smallest" => Ok(ConsolidationSelection::Smallest),
+            "uneconomical" => Ok(ConsolidationSelection::Uneconomical),
+            _ => Err(format!("unknown consolidation selection `{}`, expected smallest or uneconomical", s)),
+        }
+    }
+}
+
+/// An oracle knowing the feerates of the upcoming scenario rows, which consolidates all the UTXOs
+/// when the feerate is the lowest of the next `horizon` rows and doing it now is cheaper than
+/// spending them at the mean feerate of the withdrawals in that window.
+///
+/// No real wallet knows the future fees, so this is the upper bound of what a fee aware
+/// consolidation strategy could save.
+pub struct ClairvoyantConsolidation {
+    pub horizon: usize,
+    pub input_drain_weight: u32,
+    pub output_drain_weight: u32,
+}
+
+impl ConsolidationPolicy for ClairvoyantConsolidation {
+    fn consolidate(&mut self, scenario: &[ScenarioEntry], position: usize, utxos: &[u64]) -> Option<Vec<usize>> {
+        if utxos.len() < 2 {
+            return None;
+        }
+
+        let fee_rate = pool::sat_per_vb(scenario[position].fee_rate_per_kvb);
+        let window = &scenario[position + 1..scenario.len().min(position + 1 + self.horizon)];
+        if window.iter().any(|x| pool::sat_per_vb(x.fee_rate_per_kvb) < fee_rate) {
+            return None;
+        }
+
+        let spend_fee_rates = window
+            .iter()
+            .filter(|x| x.amount < 0.0)
+            .map(|x| pool::sat_per_vb(x.fee_rate_per_kvb))
+            .collect::<Vec<f64>>();
+        if spend_fee_rates.is_empty() {
+            return None;
+        }
+        let spend_fee_rate = spend_fee_rates.iter().sum::<f64>() / spend_fee_rates.len() as f64;
+
+        // Each input spent now instead of later saves the feerate difference, while the
+        // consolidation output has to be created now and spent later.
+        let savings = utxos.len() as f64 * SEGWIT_V1_TXIN_WEIGHT as f64 * (spend_fee_rate - fee_rate);
+        let overhead = (pool::base_weight(&[]) + self.output_drain_weight) as f64 * fee_rate
+            + self.input_drain_weight as f64 * spend_fee_rate;
+
+        if savings > overhead {
+            Some((0..utxos.len()).collect())
+        } else {
+            None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "clairvoyant"
+    }
+}
+
+/// Merges `count` UTXOs into one whenever the feerate is at or under `max_feerate`, without
+/// looking ahead. Only full sets of `count` UTXOs are merged, so a consolidation always shrinks
+/// the wallet by the same amount.
+pub struct OpportunisticConsolidation {
+    pub max_feerate: f64,
+    pub count: usize,
+    pub selection: ConsolidationSelection,
+    pub uneconomical_feerate: f64,
+}
+
+impl ConsolidationPolicy for OpportunisticConsolidation {
+    fn consolidate(&mut self, scenario: &[ScenarioEntry], position: usize, utxos: &[u64]) -> Option<Vec<usize>> {
+        if pool::sat_per_vb(scenario[position].fee_rate_per_kvb) > self.max_feerate {
+            return None;
+        }
+
+        let input_fee = SEGWIT_V1_TXIN_WEIGHT as f64 * self.uneconomical_feerate;
+        let mut candidates = (0..utxos.len())
+            .filter(|index| match self.selection {
+                ConsolidationSelection::Smallest => true,
+                ConsolidationSelection::Uneconomical => (utxos[*index] as f64) < input_fee,
+            })
+            .collect::<Vec<usize>>();
+        if candidates.len() < self.count {
+            return None;
+        }
+
+        candidates.sort_by_key(|index| utxos[*index]);
+        candidates.truncate(self.count);
+        Some(candidates)
+    }
+
+    fn name(&self) -> &'static str {
+        "opportunistic"
+    }
+}
+
+pub fn consolidation_from_name(name: &str, params: ConsolidationParams) -> Result<Box<dyn ConsolidationPolicy>, Box<dyn Error>> {
+    match name {
+        "clairvoyant" => Ok(Box::new(ClairvoyantConsolidation {
+            horizon: params.horizon,
+            input_drain_weight: params.input_drain_weight,
+            output_drain_weight: params.output_drain_weight,
+        })),
+        "opportunistic" if params.count < 2 => Err(From::from("--consolidation-count must merge at least 2 UTXOs")),
+        "opportunistic" => Ok(Box::new(OpportunisticConsolidation {
+            max_feerate: params.max_feerate,
+            count: params.count,
+            selection: params.selection,
+            uneconomical_feerate: params.uneconomical_feerate,
+        })),
+        _ => Err(format!("unknown consolidation policy `{}`, expected clairvoyant or opportunistic", name).into()),
+    }
+}
+
+/// How a transaction which missed a block is bumped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BumpStrategy {
+    /// Replaces it, selecting again the inputs to pay its payments at a higher feerate, from
+    /// its own inputs and any other UTXO.
+    Rbf,
+    /// Spends its change in a child transaction paying for both.
+    Cpfp,
+}
+
+impl FromStr for BumpStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rbf" => Ok(BumpStrategy::Rbf),
+            "cpfp" => Ok(BumpStrategy::Cpfp),
+            _ => Err(format!("unknown fee bumping `{}`, expected rbf or cpfp", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SEGWIT_V1_TXOUT_WEIGHT;
+
+    fn queue(amounts: &[u64]) -> Vec<PendingPayment> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(arrival, amount)| PendingPayment {
+                amount: *amount,
+                weight: SEGWIT_V1_TXOUT_WEIGHT,
+                arrival,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn amounts(payments: &[PendingPayment]) -> Vec<u64> {
+        payments.iter().map(|payment| payment.amount).collect()
+    }
+
+    /// Splits a queue of `amounts` with `strategy` from a single UTxO worth `funds`, without
+    /// fees, returning the amounts sent and the ones left queued.
+    fn split(strategy: PartialFulfillment, amounts_queued: &[u64], funds: u64) -> (Vec<u64>, Vec<u64>) {
+        split_within(strategy, amounts_queued, funds, 100_000)
+    }
+
+    fn split_within(strategy: PartialFulfillment, amounts_queued: &[u64], funds: u64, rounds: usize) -> (Vec<u64>, Vec<u64>) {
+        let mut queue = queue(amounts_queued);
+        let batch = strategy.split(&mut queue, &[funds], 0.0, rounds);
+        (amounts(&batch), amounts(&queue))
+    }
+
+    /// A feerate in BTC per kvB from sat/vB.
+    fn rate(sat_per_vb: f32) -> f32 {
+        sat_per_vb / 1e5
+    }
+
+    #[test]
+    fn deferral_needs_a_threshold() {
+        assert!(DeferralPolicy::new(None, None, 10, None, Urgency::Normal).is_err());
+        assert!(DeferralPolicy::new(None, Some(101.0), 10, None, Urgency::Normal).is_err());
+        assert!(DeferralPolicy::new(None, Some(50.0), 0, None, Urgency::Normal).is_err());
+    }
+
+    #[test]
+    fn rows_over_the_max_feerate_are_not_cheap() {
+        let mut deferral = DeferralPolicy::new(Some(20.0), None, 10, None, Urgency::Normal).unwrap();
+        assert!(deferral.observe(rate(10.0)));
+        assert!(!deferral.observe(rate(30.0)));
+    }
+
+    #[test]
+    fn rows_over_the_recent_percentile_are_not_cheap() {
+        let mut deferral = DeferralPolicy::new(None, Some(50.0), 4, None, Urgency::Normal).unwrap();
+        // Without recent feerates any row is cheap.
+        assert!(deferral.observe(rate(10.0)));
+        for fee_rate in [20.0, 30.0, 40.0] {
+            deferral.observe(rate(fee_rate));
+        }
+
+        // The median of 10, 20, 30 and 40 sat/vB is 20 by nearest rank.
+        assert!(!deferral.observe(rate(25.0)));
+        // 10 sat/vB left the window, so the median of 20, 30, 40 and 25 is 25.
+        assert!(deferral.observe(rate(15.0)));
+    }
+
+    #[test]
+    fn only_normal_payments_are_deferred_until_they_waited_too_long() {
+        let deferral = DeferralPolicy::new(Some(20.0), None, 10, Some(5), Urgency::Normal).unwrap();
+        let normal = PendingPayment::default();
+        let urgent = PendingPayment {
+            urgency: Some(Urgency::Urgent),
+            ..Default::default()
+        };
+
+        assert!(deferral.defers(&normal, 4, false));
+        assert!(!deferral.defers(&normal, 5, false));
+        assert!(!deferral.defers(&normal, 4, true));
+        assert!(!deferral.defers(&urgent, 0, false));
+
+        let urgent_by_default = DeferralPolicy::new(Some(20.0), None, 10, None, Urgency::Urgent).unwrap();
+        assert!(!urgent_by_default.defers(&normal, 0, false));
+    }
+
+    #[test]
+    fn the_whole_queue_is_sent_when_it_can_be_funded() {
+        for strategy in [PartialFulfillment::OldestFirst, PartialFulfillment::SmallestFirst, PartialFulfillment::MaxValue] {
+            assert_eq!(split(strategy, &[60, 40], 100), (vec![60, 40], vec![]));
+        }
+    }
+
+    #[test]
+    fn the_whole_queue_is_sent_when_no_payment_fits() {
+        for strategy in [PartialFulfillment::OldestFirst, PartialFulfillment::SmallestFirst, PartialFulfillment::MaxValue] {
+            assert_eq!(split(strategy, &[200, 150], 100), (vec![200, 150], vec![]));
+        }
+    }
+
+    #[test]
+    fn oldest_first_skips_the_payments_which_do_not_fit() {
+        assert_eq!(split(PartialFulfillment::OldestFirst, &[60, 50, 30], 100), (vec![60, 30], vec![50]));
+    }
+
+    #[test]
+    fn smallest_first_sends_the_most_payments() {
+        // The payments are sent and kept in arrival order.
+        assert_eq!(split(PartialFulfillment::SmallestFirst, &[60, 45, 10, 55], 100), (vec![45, 10], vec![60, 55]));
+    }
+
+    #[test]
+    fn max_value_sends_the_highest_amount() {
+        // Picking the biggest payment first would leave 40 sats unspent.
+        assert_eq!(split(PartialFulfillment::MaxValue, &[60, 50, 50], 100), (vec![50, 50], vec![60]));
+        assert_eq!(split(PartialFulfillment::MaxValue, &[10, 45, 30, 55, 26], 100), (vec![45, 55], vec![10, 30, 26]));
+    }
+
+    #[test]
+    fn max_value_falls_back_to_the_best_greedy_split_out_of_rounds() {
+        // Oldest first pays 85 sats and smallest first 66, instead of the 100 of 45 and 55.
+        assert_eq!(split_within(PartialFulfillment::MaxValue, &[10, 45, 30, 55, 26], 100, 1), (vec![10, 45, 30], vec![55, 26]));
+
+        // A long queue which can't be searched whole is still split.
+        let amounts = (0..60).map(|x| 1_000 + x * 37 % 101).collect::<Vec<u64>>();
+        let (sent, queued) = split_within(PartialFulfillment::MaxValue, &amounts, 30_000, 1_000);
+        assert!(!sent.is_empty() && !queued.is_empty());
+        assert!(sent.iter().sum::<u64>() <= 30_000);
+    }
+
+    #[test]
+    fn max_value_matches_every_combination() {
+        let amounts = [13, 29, 7, 41, 23, 17, 31, 11];
+        for funds in (0..=amounts.iter().sum::<u64>()).step_by(5) {
+            let best = (0..1u32 << amounts.len())
+                .map(|mask| (0..amounts.len()).filter(|i| mask & (1 << i) != 0).map(|i| amounts[i]).sum::<u64>())
+                .filter(|total| *total <= funds)
+                .max()
+                .unwrap_or_default();
+            let (sent, _) = split(PartialFulfillment::MaxValue, &amounts, funds);
+            // The whole queue is sent when no payment fits.
+            if best > 0 {
+                assert_eq!(sent.iter().sum::<u64>(), best, "funds {}", funds);
+            }
+        }
+    }
+
+    #[test]
+    fn clairvoyant_consolidation_sizes_its_output_with_the_drain_weights() {
+        let row = |amount: f64, sat_per_vb: f32| ScenarioEntry { amount, fee_rate_per_kvb: rate(sat_per_vb), urgency: None, block: None };
+        let scenario = [row(0.1, 1.0), row(-0.1, 2.0)];
+        let utxos = [10_000; 5];
+        let policy = |input_drain_weight: u32, output_drain_weight: u32| {
+            ClairvoyantConsolidation { horizon: 10, input_drain_weight, output_drain_weight }.consolidate(&scenario, 0, &utxos)
+        };
+
+        // Spending 5 inputs now saves 340 sats, more than the 177 of a P2TR output created now
+        // and spent later, but not than the 610 of a heavier one.
+        assert_eq!(policy(SEGWIT_V1_TXIN_WEIGHT, SEGWIT_V1_TXOUT_WEIGHT), Some(vec![0, 1, 2, 3, 4]));
+        assert_eq!(policy(200, 200), None);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::SEGWIT_V1_TXOUT_WEIGHT;
+use crate::models::{ PendingPayment, TransactionType };
+use crate::selectors::{ pool, TargetSelector };
+use crate::selectors::pool::WasteParams;
+
+use std::{
+    error::Error,
+    fs::File,
+};
+
+use bitcoin::amount::Amount;
+
+/// The fields of a `full_results.csv` row needed to replay its withdrawal.
+#[derive(Debug, serde::Deserialize)]
+struct RecordedWithdrawal {
+    id: usize,
+    #[serde(default)]
+    transaction_type: TransactionType,
+    amount: u64,
+    /// Missing from the runs made before the payments were recorded one by one.
+    #[serde(default)]
+    payment_amounts: Option<String>,
+    fee: Option<i64>,
+    #[serde(default)]
+    fee_rate_per_kvb: Option<f32>,
+    algorithm: String,
+    input_count: Option<usize>,
+    change_amount: Option<u64>,
+}
+
+impl RecordedWithdrawal {
+    fn read(run_path: &str, attempt: usize) -> Result<Self, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(File::open(format!("{}/full_results.csv", run_path))?);
+
+        for result in reader.deserialize() {
+            let withdrawal: RecordedWithdrawal = result?;
+            if withdrawal.id == attempt {
+                return Ok(withdrawal);
+            }
+        }
+
+        Err(format!("attempt {} not found in {}/full_results.csv", attempt, run_path).into())
+    }
+
+    /// The payments funded by the withdrawal, as given to the selector.
+    fn payments(&self) -> Result<Vec<PendingPayment>, Box<dyn Error>> {
+        let amounts = self.payment_amounts
+            .as_deref()
+            .ok_or_else(|| format!("attempt {} doesn't record its payment amounts, simulate it again to replay it", self.id))?;
+
+        amounts
+            .split(',')
+            .filter(|x| !x.is_empty())
+            .map(|x| Ok(PendingPayment {
+                amount: x.parse::<u64>()?,
+                weight: SEGWIT_V1_TXOUT_WEIGHT,
+                ..Default::default()
+            }))
+            .collect()
+    }
+
+    /// The scenario feerate given to the selector, in BTC per kvB.
+    fn fee_rate_per_kvb(&self) -> Result<f32, Box<dyn Error>> {
+        self.fee_rate_per_kvb
+            .ok_or_else(|| format!("attempt {} doesn't record its scenario feerate, simulate it again to replay it", self.id).into())
+    }
+}
+
+/// The UTXO values the wallet had before the withdrawal `attempt`, as saved in `utxos.csv`.
+fn utxo_snapshot(run_path: &str, attempt: usize) -> Result<Vec<u64>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(File::open(format!("{}/utxos.csv", run_path))?);
+
+    for result in reader.deserialize() {
+        let (id, values): (usize, String) = result?;
+        if id == attempt {
+            return values
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .map(|x| Ok(x.parse::<u64>()?))
+                .collect();
+        }
+    }
+
+    Err(format!("attempt {} not found in {}/utxos.csv", attempt, run_path).into())
+}
+
+/// Runs `selector` on the UTXOs and payments of the withdrawal `attempt` recorded in `run_path`,
+/// printing its selection next to the recorded one.
+pub fn replay(run_path: &str, attempt: usize, backend: &str, selector: &mut dyn TargetSelector, waste_params: &WasteParams) -> Result<(), Box<dyn Error>> {
+    let recorded = RecordedWithdrawal::read(run_path, attempt)?;
+    match recorded.transaction_type {
+        TransactionType::Payment => (),
+        TransactionType::Consolidation => return Err(format!("attempt {} is a consolidation, not a withdrawal", attempt).into()),
+        TransactionType::FeeBump => return Err(format!("attempt {} is a fee bump, not a withdrawal", attempt).into()),
+    }
+    let utxos = utxo_snapshot(run_path, attempt)?;
+    let payments = recorded.payments()?;
+    let fee_rate_per_kvb = recorded.fee_rate_per_kvb()?;
+
+    println!("attempt {} of {}", attempt, run_path);
+    println!("utxos: {} worth {}", utxos.len(), Amount::from_sat(utxos.iter().sum()));
+    println!(
+        "payments: {} worth {} at {} sat/vB",
+        payments.len(),
+        Amount::from_sat(recorded.amount),
+        pool::sat_per_vb(fee_rate_per_kvb)
+    );
+    println!(
+        "recorded: {}, {} inputs, fee {}, change {}",
+        recorded.algorithm,
+        recorded.input_count.unwrap_or(0),
+        recorded.fee.map_or(String::from("none"), |x| x.to_string()),
+        recorded.change_amount.map_or(String::from("none"), |x| x.to_string())
+    );
+
+    selector.load(&utxos)?;
+    let entry = selector.withdraw(&payments, fee_rate_per_kvb)?;
+
+    println!("replayed with {}: {}", backend, entry.algorithm);
+    for input in entry.inputs.iter() {
+        println!("  input {}", input);
+    }
+    println!("  fee: {}", entry.fee.map_or(String::from("none"), |x| x.to_string()));
+    println!("  real feerate: {}", entry.real_feerate.map_or(String::from("none"), |x| x.to_string()));
+    println!("  change: {}", entry.change_amount.map_or(String::from("none"), |x| x.to_string()));
+
+    if let Some((input_waste, change_waste)) = pool::waste_breakdown(&entry, fee_rate_per_kvb, waste_params) {
+        let change_kind = if entry.change_amount.is_some() { "change" } else { "excess" };
+        println!(
+            "  waste: {} = {} from inputs + {} from {}",
+            input_waste + change_waste,
+            input_waste,
+            change_waste,
+            change_kind
+        );
+    }
+
+    for (name, value) in selector.statistics() {
+        println!("  {}: {}", name, value);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use crate::SEGWIT_V1_TXOUT_WEIGHT;
+use crate::models::PendingPayment;
+use crate::selectors::external::{ exchange, withdraw_request, Response, PROTOCOL_VERSION };
+use crate::selectors::pool::CandidatePool;
+use crate::selectors::worker::Worker;
+
+use std::{
+    error::Error,
+    process::Command,
+    time::Duration,
+};
+
+use serde_json::json;
+
+const DEPOSITS: [u64; 4] = [50_000, 120_000, 300_000, 1_000_000];
+// 10 sat/vB
+const FEE_RATE_PER_KVB: f32 = 0.0001;
+
+fn check_deposits(worker: &mut Worker, pool: &mut CandidatePool) -> Result<(), Box<dyn Error>> {
+    for value in DEPOSITS {
+        match exchange(worker, json!({ "type": "deposit", "value": value }))? {
+            Response::Ack => pool.push(value),
+            response => return Err(format!("expected ack, but got {:?}", response).into()),
+        }
+    }
+    Ok(())
+}
+
+fn check_load(worker: &mut Worker, pool: &mut CandidatePool) -> Result<(), Box<dyn Error>> {
+    // The same UTXOs are loaded back, so the following checks see the deposits.
+    match exchange(worker, json!({ "type": "load", "values": DEPOSITS }))? {
+        Response::Ack => {
+            pool.load(&DEPOSITS);
+            Ok(())
+        },
+        response => Err(format!("expected ack, but got {:?}", response).into()),
+    }
+}
+
+fn check_fundable_withdraw(worker: &mut Worker, pool: &mut CandidatePool) -> Result<(), Box<dyn Error>> {
+    let payments = [PendingPayment { amount: 400_000, weight: SEGWIT_V1_TXOUT_WEIGHT, ..Default::default() }];
+    match exchange(worker, withdraw_request(pool, &payments, FEE_RATE_PER_KVB))? {
+        Response::Selection { selected, .. } => {
+            let mut unique = selected.clone();
+            unique.sort();
+            unique.dedup();
+            if unique.len() != selected.len() {
+                return Err(format!("selection {:?} repeats candidates", selected).into());
+            }
+            // Accounting the selection on a copy of the pool checks it pays the target and fees.
+            pool.clone().apply(&selected, &payments, FEE_RATE_PER_KVB, "conformance")?;
+            Ok(())
+        },
+        response => Err(format!("expected selection, but got {:?}", response).into()),
+    }
+}
+
+fn check_unfundable_withdraw(worker: &mut Worker, pool: &mut CandidatePool) -> Result<(), Box<dyn Error>> {
+    let payments = [PendingPayment { amount: DEPOSITS.iter().sum::<u64>(), weight: SEGWIT_V1_TXOUT_WEIGHT, ..Default::default() }];
+    match exchange(worker, withdraw_request(pool, &payments, FEE_RATE_PER_KVB))? {
+        Response::Error { .. } => Ok(()),
+        response => Err(format!("expected error, but got {:?}", response).into()),
+    }
+}
+
+fn check_unsupported_version(worker: &mut Worker, _: &mut CandidatePool) -> Result<(), Box<dyn Error>> {
+    let response = worker.request(&json!({ "version": PROTOCOL_VERSION + 1, "type": "deposit", "value": 1_000 }))?;
+    match response.get("type").and_then(|x| x.as_str()) {
+        Some("error") => Ok(()),
+        _ => Err(format!("expected error, but got {}", response).into()),
+    }
+}
+
+fn check_unknown_message(worker: &mut Worker, _: &mut CandidatePool) -> Result<(), Box<dyn Error>> {
+    match exchange(worker, json!({ "type": "unknown" }))? {
+        Response::Error { .. } => Ok(()),
+        response => Err(format!("expected error, but got {:?}", response).into()),
+    }
+}
+
+type Check = fn(&mut Worker, &mut CandidatePool) -> Result<(), Box<dyn Error>>;
+
+/// Runs `command` through a fixed exchange of protocol messages, printing the outcome of each
+/// check. Returns whether the selector passed all of them.
+pub fn check(command: &[String], timeout: Duration) -> Result<bool, Box<dyn Error>> {
+    let (program, args) = command
+        .split_first()
+        .ok_or("the conformance checker needs the selector command to run")?;
+    let mut worker = Worker::spawn(Command::new(program).args(args), timeout)?;
+    let mut pool = CandidatePool::new(10.0, 526, crate::SEGWIT_V1_TXIN_WEIGHT, SEGWIT_V1_TXOUT_WEIGHT);
+
+    let checks: [(&str, Check); 6] = [
+        ("acknowledges deposits", check_deposits),
+        ("acknowledges loads", check_load),
+        ("selects a fundable withdrawal", check_fundable_withdraw),
+        ("rejects an unfundable withdrawal", check_unfundable_withdraw),
+        ("rejects unsupported protocol versions", check_unsupported_version),
+        ("rejects unknown messages", check_unknown_message),
+    ];
+
+    let mut passed = true;
+    for (name, check) in checks {
+        match check(&mut worker, &mut pool) {
+            Ok(()) => println!("ok     {}", name),
+            Err(err) => {
+                println!("FAILED {}: {}", name, err);
+                passed = false;
+            },
+        }
+    }
+
+    Ok(passed)
+}
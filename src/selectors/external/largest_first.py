@@ -0,0 +1,45 @@
+#!/usr/bin/env python3
+"""Reference implementation of the external selector protocol.
+
+Selects the largest candidates first until the payments and the fees are covered. Run it with:
+
+    bdk-coin-select-simulation <scenario> <output> external -- python3 largest_first.py
+"""
+import json
+import math
+import sys
+
+PROTOCOL_VERSION = 2
+
+
+def select(request: dict) -> dict:
+    target = sum(payment["amount"] for payment in request["payments"])
+    weight = request["base_weight"]
+    selected = []
+    selected_value = 0
+
+    for candidate in sorted(request["candidates"], key=lambda x: x["value"], reverse=True):
+        selected.append(candidate["index"])
+        selected_value += candidate["value"]
+        weight += candidate["weight"]
+        if selected_value >= target + math.ceil(weight * request["fee_rate"]):
+            return {"type": "selection", "selected": selected, "algorithm": "largest_first"}
+
+    return {"type": "error", "message": "insufficient funds"}
+
+
+def handle(request: dict) -> dict:
+    if request.get("version") != PROTOCOL_VERSION:
+        return {"type": "error", "message": f"unsupported protocol version {request.get('version')}"}
+    if request.get("type") in ("deposit", "load"):
+        return {"type": "ack"}
+    if request.get("type") == "withdraw":
+        return select(request)
+    return {"type": "error", "message": f"unknown message type {request.get('type')}"}
+
+
+for line in sys.stdin:
+    response = handle(json.loads(line))
+    response["version"] = PROTOCOL_VERSION
+    sys.stdout.write(json.dumps(response) + "\n")
+    sys.stdout.flush()
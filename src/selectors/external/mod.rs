@@ -0,0 +1,158 @@
+pub mod conformance;
+
+use crate::selectors::TargetSelector;
+use crate::selectors::pool::{ self, CandidatePool };
+use crate::selectors::worker::Worker;
+use crate::models::{ PendingPayment, ScenarioEntry, SimulationEntry };
+
+use std::{
+    error::Error,
+    process::Command,
+    time::Duration,
+};
+
+use bitcoin::amount::Amount;
+use serde_json::{ json, Value };
+use tracing::{ Level, event };
+
+/// Version of the external selector protocol spoken by the simulator. Selectors must answer
+/// messages of any other version with an error.
+pub const PROTOCOL_VERSION: u64 = 2;
+
+/// The answers a selector may give to the simulator messages.
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    /// A deposit or a load was registered.
+    Ack,
+    /// Indices of the candidates to spend for a withdrawal.
+    Selection {
+        selected: Vec<usize>,
+        #[serde(default)]
+        algorithm: Option<String>,
+    },
+    /// The request couldn't be fulfilled, for a withdrawal this means no selection was found.
+    Error { message: String },
+}
+
+/// A selector implemented by any local executable speaking the external selector protocol.
+///
+/// The protocol is a sequence of JSON objects, one per line, each one carrying the protocol
+/// `version` and its `type`. The simulator writes requests to the selector standard input and
+/// reads one response per request from its standard output:
+/// - `deposit`, with the `value` in sats of the new UTXO, answered by an `ack`.
+/// - `load`, with the `values` in sats of the UTXOs replacing the whole wallet, answered by an
+///   `ack`.
+/// - `withdraw`, with the `candidates` the wallet can spend, the `payments` to make, the
+///   `fee_rate` and `long_term_fee_rate` in sat/vB and the `base_weight` of the transaction
+///   without inputs, answered by a `selection` holding the `selected` candidate indices and
+///   optionally the name of the `algorithm` that found them.
+/// - Any request may be answered by an `error` with a `message`. When withdrawing it's accounted
+///   as a failed selection.
+///
+/// The simulator keeps the wallet UTXOs and builds the transactions, adding a change output when
+/// it's worth it. Selections which don't pay the payments and fees are accounted as failed.
+pub struct ExternalCoinSelect {
+    worker: Worker,
+    pool: CandidatePool,
+}
+
+impl ExternalCoinSelect {
+    pub fn new(command: &[String], timeout: Duration, long_term_feerate: f32, dust_limit: u64, input_drain_weight: u32, output_drain_weight: u32) -> Result<Self, Box<dyn Error>> {
+        let (program, args) = command
+            .split_first()
+            .ok_or("the external selector needs a command to run")?;
+
+        Ok(ExternalCoinSelect {
+            worker: Worker::spawn(Command::new(program).args(args), timeout)?,
+            pool: CandidatePool::new(long_term_feerate, dust_limit, input_drain_weight, output_drain_weight),
+        })
+    }
+}
+
+/// Sends a protocol `message` to the selector, checking the response speaks the same version.
+pub fn exchange(worker: &mut Worker, mut message: Value) -> Result<Response, Box<dyn Error>> {
+    message["version"] = json!(PROTOCOL_VERSION);
+    let response = worker.request(&message)?;
+
+    match response.get("version").and_then(Value::as_u64) {
+        Some(PROTOCOL_VERSION) => Ok(serde_json::from_value(response)?),
+        version => Err(format!("expected protocol version {}, but got {:?}", PROTOCOL_VERSION, version).into()),
+    }
+}
+
+/// The `withdraw` message asking to fund `payments` from `pool`.
+pub fn withdraw_request(pool: &CandidatePool, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Value {
+    let candidates = pool
+        .values()
+        .iter()
+        .enumerate()
+        .map(|(index, value)| json!({ "index": index, "value": value, "weight": crate::SEGWIT_V1_TXIN_WEIGHT }))
+        .collect::<Vec<Value>>();
+
+    json!({
+        "type": "withdraw",
+        "candidates": candidates,
+        "payments": payments,
+        "fee_rate": pool::sat_per_vb(fee_rate_per_kvb),
+        "long_term_fee_rate": pool.long_term_feerate,
+        "base_weight": pool::base_weight(payments),
+        "dust_limit": pool.dust_limit,
+    })
+}
+
+impl TargetSelector for ExternalCoinSelect {
+    fn values(&self) -> Result<Vec<u64>, Box<dyn Error>> {
+        Ok(self.pool.values().to_vec())
+    }
+
+    fn deposit(&mut self, deposit: ScenarioEntry) -> Result<(), Box<dyn Error>> {
+        let value = Amount::from_btc(deposit.amount)?.to_sat();
+
+        match exchange(&mut self.worker, json!({ "type": "deposit", "value": value }))? {
+            Response::Ack => {
+                self.pool.push(value);
+                Ok(())
+            },
+            Response::Error { message } => Err(format!("external selector rejected deposit: {}", message).into()),
+            response => Err(format!("expected ack for deposit, but got {:?}", response).into()),
+        }
+    }
+
+    fn load(&mut self, values: &[u64]) -> Result<(), Box<dyn Error>> {
+        match exchange(&mut self.worker, json!({ "type": "load", "values": values }))? {
+            Response::Ack => {
+                self.pool.load(values);
+                Ok(())
+            },
+            Response::Error { message } => Err(format!("external selector rejected load: {}", message).into()),
+            response => Err(format!("expected ack for load, but got {:?}", response).into()),
+        }
+    }
+
+    /// The command may select at random, there's no telling.
+    fn randomized(&self) -> bool {
+        true
+    }
+
+    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Result<SimulationEntry, Box<dyn Error>> {
+        match exchange(&mut self.worker, withdraw_request(&self.pool, payments, fee_rate_per_kvb))? {
+            Response::Selection { selected, algorithm } => {
+                let algorithm = algorithm.unwrap_or_else(|| String::from("external"));
+                // The pool is left untouched by an invalid selection, so the simulation goes on.
+                match self.pool.apply(&selected, payments, fee_rate_per_kvb, &algorithm) {
+                    Ok(entry) => Ok(entry),
+                    Err(err) => {
+                        event!(Level::ERROR, "external selector made an invalid selection: {}", err);
+                        Ok(self.pool.entry(payments, fee_rate_per_kvb))
+                    },
+                }
+            },
+            Response::Error { message } => {
+                event!(Level::INFO, "external selector found no selection: {}", message);
+                Ok(self.pool.entry(payments, fee_rate_per_kvb))
+            },
+            response => Err(format!("expected selection for withdraw, but got {:?}", response).into()),
+        }
+    }
+}
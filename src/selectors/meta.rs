@@ -0,0 +1,134 @@
+use crate::selectors::{ pool, TargetSelector };
+use crate::selectors::pool::WasteParams;
+use crate::models::{ PendingPayment, ScenarioEntry, SimulationEntry };
+
+use std::{
+    error::Error,
+    str::FromStr,
+};
+
+use bitcoin::amount::Amount;
+
+/// How the results of the wrapped selectors are ranked, lower is better.
+#[derive(Debug, Default, Clone, Copy)]
+pub enum SelectionScore {
+    #[default]
+    Waste,
+    Fee,
+    InputCount,
+}
+
+impl FromStr for SelectionScore {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waste" => Ok(SelectionScore::Waste),
+            "fee" => Ok(SelectionScore::Fee),
+            "input-count" => Ok(SelectionScore::InputCount),
+            _ => Err(format!("unknown selection score `{}`, expected waste, fee or input-count", s)),
+        }
+    }
+}
+
+impl SelectionScore {
+    fn score(&self, entry: &SimulationEntry, fee_rate_per_kvb: f32, waste_params: &WasteParams) -> Option<f64> {
+        match self {
+            SelectionScore::Waste => pool::waste(entry, fee_rate_per_kvb, waste_params),
+            SelectionScore::Fee => entry.fee.map(|x| x as f64),
+            SelectionScore::InputCount => Some(entry.inputs.len() as f64),
+        }
+    }
+}
+
+/// Runs every wrapped selector on the same UTXOs and keeps the best scored selection, as Bitcoin
+/// Core does with its algorithms.
+///
+/// The winner is recorded in `algorithm` as `<selector>:<algorithm>`. Ties go to the selector
+/// listed first.
+pub struct MetaCoinSelect {
+    selectors: Vec<(String, Box<dyn TargetSelector>)>,
+    /// Index of the selector which won the last withdrawal.
+    winner: Option<usize>,
+    values: Vec<u64>,
+    score: SelectionScore,
+    waste_params: WasteParams,
+}
+
+impl MetaCoinSelect {
+    pub fn new(selectors: Vec<(String, Box<dyn TargetSelector>)>, score: SelectionScore, waste_params: WasteParams) -> Self {
+        MetaCoinSelect {
+            selectors,
+            winner: None,
+            values: Vec::default(),
+            score,
+            waste_params,
+        }
+    }
+}
+
+impl TargetSelector for MetaCoinSelect {
+    fn values(&self) -> Result<Vec<u64>, Box<dyn Error>> {
+        Ok(self.values.clone())
+    }
+
+    fn deposit(&mut self, deposit: ScenarioEntry) -> Result<(), Box<dyn Error>> {
+        self.values.push(Amount::from_btc(deposit.amount)?.to_sat());
+        Ok(())
+    }
+
+    fn load(&mut self, values: &[u64]) -> Result<(), Box<dyn Error>> {
+        self.values = values.to_vec();
+        Ok(())
+    }
+
+    fn statistics(&self) -> Vec<(String, String)> {
+        match self.winner {
+            Some(index) => {
+                let (name, selector) = &self.selectors[index];
+                let mut statistics = vec![(String::from("winner"), name.clone())];
+                statistics.extend(selector.statistics());
+                statistics
+            },
+            None => Vec::new(),
+        }
+    }
+
+    fn randomized(&self) -> bool {
+        self.selectors.iter().any(|(_, selector)| selector.randomized())
+    }
+
+    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Result<SimulationEntry, Box<dyn Error>> {
+        self.winner = None;
+        let mut failed = None;
+        let mut best: Option<(usize, f64, SimulationEntry)> = None;
+
+        for (index, (_, selector)) in self.selectors.iter_mut().enumerate() {
+            selector.load(&self.values)?;
+            let entry = selector.withdraw(payments, fee_rate_per_kvb)?;
+
+            let score = match self.score.score(&entry, fee_rate_per_kvb, &self.waste_params) {
+                Some(score) if entry.algorithm != "failed" => score,
+                _ => {
+                    failed.get_or_insert(entry);
+                    continue;
+                },
+            };
+
+            if best.as_ref().is_none_or(|(_, best_score, _)| score < *best_score) {
+                best = Some((index, score, entry));
+            }
+        }
+
+        match best {
+            Some((index, _, mut entry)) => {
+                let (name, selector) = &self.selectors[index];
+                self.values = selector.values()?;
+                self.winner = Some(index);
+                entry.algorithm = format!("{}:{}", name, entry.algorithm);
+                Ok(entry)
+            },
+            None => Ok(failed.expect("there should be at least one selector.")),
+        }
+    }
+}
@@ -1,12 +1,273 @@
 pub mod bdk;
+pub mod external;
+pub mod meta;
+pub mod optimal;
+pub mod pool;
 pub mod rust_coinselect;
 pub mod python;
+#[cfg(feature = "script")]
+pub mod script;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod worker;
 
-use std::error::Error;
-use crate::{ ScenarioEntry, SimulationEntry, PendingPayment };
+use std::{
+    error::Error,
+    time::Duration,
+};
+use crate::{ ScenarioEntry, SimulationEntry, PendingPayment, SEGWIT_V1_TXIN_WEIGHT, SEGWIT_V1_TXOUT_WEIGHT };
+use crate::selectors::external::ExternalCoinSelect;
+use crate::selectors::meta::{ MetaCoinSelect, SelectionScore };
+use crate::selectors::optimal::{ Objective, OptimalCoinSelect };
+use crate::selectors::pool::WasteParams;
+use crate::selectors::rust_coinselect::RustCoinSelect;
+use crate::selectors::python::bitcoin_coin_selection::PythonCoinSelect;
+#[cfg(feature = "script")]
+use crate::selectors::script::ScriptCoinSelect;
+#[cfg(feature = "wasm")]
+use crate::selectors::wasm::WasmCoinSelect;
 
 pub trait TargetSelector {
     fn deposit(&mut self, deposit: ScenarioEntry) -> Result<(), Box<dyn Error>>;
-    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> SimulationEntry;
-    fn values(&self) -> Vec<u64>;
+    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Result<SimulationEntry, Box<dyn Error>>;
+    fn values(&self) -> Result<Vec<u64>, Box<dyn Error>>;
+    /// Replaces the wallet UTXOs with `values`, so several selectors can be run on the same
+    /// snapshot.
+    fn load(&mut self, values: &[u64]) -> Result<(), Box<dyn Error>>;
+    /// Backend specific statistics about the last withdrawal, such as the rounds a search took,
+    /// as name and value pairs.
+    fn statistics(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+    /// Whether the selections draw from a random state, so that running a selection which isn't
+    /// recorded changes the ones after it.
+    fn randomized(&self) -> bool {
+        false
+    }
+}
+
+/// Parameters shared by all the selector backends, each one uses the subset it understands.
+#[derive(Debug, Clone)]
+pub struct SelectorParams {
+    pub long_term_feerate: f32,
+    pub dust_limit: u64,
+    pub input_drain_weight: u32,
+    pub output_drain_weight: u32,
+    /// Rounds of branch and bound each `bdk` selection is allowed to run.
+    pub bnb_rounds: usize,
+    /// Seed of the randomized selection algorithms, picked by them when not given.
+    pub seed: Option<u64>,
+    pub python_interpreter: String,
+    pub worker_timeout: Duration,
+    /// Program and arguments of the `external` selector.
+    pub external_command: Vec<String>,
+    /// Path of the module run by the `wasm` selector.
+    pub wasm_module: String,
+    /// Units of execution given to each `wasm` selection.
+    pub wasm_fuel: u64,
+    /// Bytes of memory the `wasm` module may grow to.
+    pub wasm_max_memory: usize,
+    /// Path of the Rhai script run by the `script` selector.
+    pub script: String,
+    /// Operations each `script` selection is allowed to run.
+    pub script_max_operations: u64,
+    /// Backends run by the `meta` selector.
+    pub meta_selectors: Vec<String>,
+    /// How the `meta` selector picks the winning selection.
+    pub meta_score: SelectionScore,
+    /// Biggest UTXO pool the `optimal` selector searches exhaustively.
+    pub optimal_max_utxos: usize,
+    /// What the `optimal` selector minimizes.
+    pub optimal_objective: Objective,
+}
+
+impl Default for SelectorParams {
+    fn default() -> SelectorParams {
+        SelectorParams {
+            long_term_feerate: 10.0,
+            dust_limit: 526,
+            input_drain_weight: SEGWIT_V1_TXIN_WEIGHT,
+            output_drain_weight: SEGWIT_V1_TXOUT_WEIGHT,
+            bnb_rounds: 100_000,
+            seed: None,
+            python_interpreter: String::from("python3"),
+            worker_timeout: Duration::from_secs(60),
+            external_command: Vec::new(),
+            wasm_module: String::default(),
+            wasm_fuel: 100_000_000,
+            wasm_max_memory: 64 << 20,
+            script: String::default(),
+            script_max_operations: 10_000_000,
+            meta_selectors: vec![String::from("bdk"), String::from("rust-coinselect")],
+            meta_score: SelectionScore::default(),
+            optimal_max_utxos: 20,
+            optimal_objective: Objective::default(),
+        }
+    }
+}
+
+impl SelectorParams {
+    /// The parameters the backends are asked to measure waste with.
+    pub fn waste_params(&self) -> WasteParams {
+        WasteParams {
+            long_term_feerate: self.long_term_feerate,
+            input_drain_weight: self.input_drain_weight,
+            output_drain_weight: self.output_drain_weight,
+        }
+    }
+
+    /// Names of the parameters that can be changed through [`SelectorParams::set`].
+    pub const NAMES: [&'static str; 17] = [
+        "long-term-feerate",
+        "dust-limit",
+        "input-drain-weight",
+        "output-drain-weight",
+        "bnb-rounds",
+        "seed",
+        "python-interpreter",
+        "worker-timeout",
+        "wasm-module",
+        "wasm-fuel",
+        "wasm-max-memory",
+        "script",
+        "script-max-operations",
+        "meta-selectors",
+        "meta-score",
+        "optimal-max-utxos",
+        "optimal-objective",
+    ];
+
+    /// Sets the parameter `name` from its textual `value`, the timeout is given in seconds.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        match name {
+            "long-term-feerate" => self.long_term_feerate = value.parse()?,
+            "dust-limit" => self.dust_limit = value.parse()?,
+            "input-drain-weight" => self.input_drain_weight = value.parse()?,
+            "output-drain-weight" => self.output_drain_weight = value.parse()?,
+            "bnb-rounds" => self.bnb_rounds = value.parse()?,
+            "seed" => self.seed = Some(value.parse()?),
+            "python-interpreter" => self.python_interpreter = value.to_string(),
+            "worker-timeout" => self.worker_timeout = Duration::from_secs_f64(value.parse()?),
+            "wasm-module" => self.wasm_module = value.to_string(),
+            "wasm-fuel" => self.wasm_fuel = value.parse()?,
+            "wasm-max-memory" => self.wasm_max_memory = value.parse::<usize>()? << 20,
+            "script" => self.script = value.to_string(),
+            "script-max-operations" => self.script_max_operations = value.parse()?,
+            "meta-selectors" => self.meta_selectors = value.split(',').map(String::from).collect(),
+            "meta-score" => self.meta_score = value.parse()?,
+            "optimal-max-utxos" => self.optimal_max_utxos = value.parse()?,
+            "optimal-objective" => self.optimal_objective = value.parse()?,
+            _ => return Err(format!("unknown selector parameter `{}`", name).into()),
+        };
+
+        Ok(())
+    }
+}
+
+/// Names of the selector backends compiled into this binary.
+pub fn available_backends() -> Vec<&'static str> {
+    let mut backends = vec!["bdk", "bdk-master"];
+    if cfg!(feature = "bdk-v0_3") {
+        backends.push("bdk-v0.3");
+    }
+    backends.push("rust-coinselect");
+    if cfg!(feature = "python") {
+        backends.push("python");
+    }
+    backends.push("python-worker");
+    backends.push("external");
+    if cfg!(feature = "wasm") {
+        backends.push("wasm");
+    }
+    if cfg!(feature = "script") {
+        backends.push("script");
+    }
+    backends.push("meta");
+    backends.push("optimal");
+    backends
+}
+
+/// The backend used when none is requested, the embedded Python selector if it was compiled in.
+pub fn default_backend() -> &'static str {
+    if cfg!(feature = "python") {
+        "python"
+    } else {
+        "python-worker"
+    }
+}
+
+pub fn from_name(name: &str, params: &SelectorParams) -> Result<Box<dyn TargetSelector>, Box<dyn Error>> {
+    let selector: Box<dyn TargetSelector> = match name {
+        "bdk" | "bdk-master" => Box::new(bdk::master::BdkCoinSelect::new(params.long_term_feerate, params.dust_limit, params.input_drain_weight, params.output_drain_weight, params.bnb_rounds)),
+        #[cfg(feature = "bdk-v0_3")]
+        "bdk-v0.3" => Box::new(bdk::v0_3::BdkCoinSelect::new(params.long_term_feerate, params.dust_limit, params.input_drain_weight, params.output_drain_weight, params.bnb_rounds)),
+        #[cfg(not(feature = "bdk-v0_3"))]
+        "bdk-v0.3" => return Err(From::from("the `bdk-v0.3` backend wasn't compiled in, rebuild with `--features bdk-v0_3`")),
+        "rust-coinselect" => Box::new(RustCoinSelect::new(params.long_term_feerate, params.dust_limit, params.input_drain_weight, params.output_drain_weight)),
+        #[cfg(feature = "python")]
+        "python" => Box::new(PythonCoinSelect::new(params.long_term_feerate, params.dust_limit, params.input_drain_weight, params.output_drain_weight, params.seed)?),
+        "python-worker" => Box::new(PythonCoinSelect::new_isolated(
+            params.long_term_feerate,
+            params.dust_limit,
+            params.input_drain_weight,
+            params.output_drain_weight,
+            params.seed,
+            &params.python_interpreter,
+            params.worker_timeout,
+        )?),
+        "external" => Box::new(ExternalCoinSelect::new(
+            &params.external_command,
+            params.worker_timeout,
+            params.long_term_feerate,
+            params.dust_limit,
+            params.input_drain_weight,
+            params.output_drain_weight,
+        )?),
+        "meta" => {
+            if params.meta_selectors.is_empty() || params.meta_selectors.iter().any(|x| x == "meta") {
+                return Err(From::from("the `meta` selector needs a list of other backends to run"));
+            }
+            let selectors = params.meta_selectors
+                .iter()
+                .map(|name| Ok((name.clone(), from_name(name, params)?)))
+                .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+            Box::new(MetaCoinSelect::new(selectors, params.meta_score, params.waste_params()))
+        },
+        "optimal" => Box::new(OptimalCoinSelect::new(
+            params.optimal_max_utxos,
+            params.optimal_objective,
+            params.long_term_feerate,
+            params.dust_limit,
+            params.input_drain_weight,
+            params.output_drain_weight,
+        )),
+        #[cfg(feature = "wasm")]
+        "wasm" => Box::new(WasmCoinSelect::new(
+            &params.wasm_module,
+            params.wasm_fuel,
+            params.wasm_max_memory,
+            params.long_term_feerate,
+            params.dust_limit,
+            params.input_drain_weight,
+            params.output_drain_weight,
+        )?),
+        #[cfg(not(feature = "wasm"))]
+        "wasm" => return Err(From::from("the `wasm` backend wasn't compiled in, rebuild with `--features wasm`")),
+        #[cfg(feature = "script")]
+        "script" => Box::new(ScriptCoinSelect::new(
+            &params.script,
+            params.script_max_operations,
+            params.long_term_feerate,
+            params.dust_limit,
+            params.input_drain_weight,
+            params.output_drain_weight,
+        )?),
+        #[cfg(not(feature = "script"))]
+        "script" => return Err(From::from("the `script` backend wasn't compiled in, rebuild with `--features script`")),
+        #[cfg(not(feature = "python"))]
+        "python" => return Err(From::from("the `python` backend wasn't compiled in, rebuild with `--features python` or use `python-worker`")),
+        _ => return Err(format!("unknown backend `{}`, available backends: {}", name, available_backends().join(", ")).into()),
+    };
+
+    Ok(selector)
 }
@@ -0,0 +1,315 @@
+use crate::SEGWIT_V1_TXIN_WEIGHT;
+use crate::selectors::TargetSelector;
+use crate::selectors::pool::{ self, CandidatePool, WasteParams };
+use crate::models::{ PendingPayment, ScenarioEntry, SimulationEntry };
+
+use std::{
+    cmp::Reverse,
+    error::Error,
+    str::FromStr,
+};
+
+use bitcoin::amount::Amount;
+
+/// What the `optimal` selector minimizes.
+#[derive(Debug, Default, Clone, Copy)]
+pub enum Objective {
+    /// The waste of the selection, as computed by [`pool::waste`].
+    #[default]
+    Waste,
+    /// The fee paid now plus the fee of spending the change output at the long term feerate.
+    LowestFee,
+}
+
+impl FromStr for Objective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waste" => Ok(Objective::Waste),
+            "lowest-fee" => Ok(Objective::LowestFee),
+            _ => Err(format!("unknown objective `{}`, expected waste or lowest-fee", s)),
+        }
+    }
+}
+
+impl Objective {
+    /// Cost of a transaction spending `input_count` inputs which pays `fee`, with or without
+    /// change.
+    fn cost(&self, input_count: usize, output_count: usize, fee: i64, has_change: bool, fee_rate_per_kvb: f32, waste_params: &WasteParams) -> f64 {
+        match self {
+            Objective::Waste => {
+                let (input_waste, change_waste) = pool::transaction_waste(input_count, output_count, fee, has_change, fee_rate_per_kvb, waste_params);
+                input_waste + change_waste
+            },
+            Objective::LowestFee if has_change => fee as f64 + waste_params.input_drain_weight as f64 * waste_params.long_term_feerate as f64,
+            Objective::LowestFee => fee as f64,
+        }
+    }
+
+    /// Cost of the transaction recorded in `entry`, or `None` if the selection failed. Computed
+    /// alike for all the backends, so their distance to the optimum can be compared.
+    pub fn score(&self, entry: &SimulationEntry, fee_rate_per_kvb: f32, waste_params: &WasteParams) -> Option<f64> {
+        let fee = entry.fee?;
+        if entry.algorithm == "failed" {
+            return None;
+        }
+
+        Some(self.cost(
+            entry.inputs.len(),
+            entry.output_count.unwrap_or(1),
+            fee,
+            entry.change_amount.is_some(),
+            fee_rate_per_kvb,
+            waste_params,
+        ))
+    }
+}
+
+/// The withdrawal being searched: the candidates sorted by decreasing value and the best
+/// selection found so far.
+struct Search<'a> {
+    pool: &'a CandidatePool,
+    payments: &'a [PendingPayment],
+    fee_rate_per_kvb: f32,
+    objective: Objective,
+    /// Indices of the candidates in the pool, by decreasing value.
+    order: Vec<usize>,
+    /// Value of the candidates from each position of `order` to the end.
+    remaining_value: Vec<u64>,
+    amount: u64,
+    selection: Vec<usize>,
+    best: Option<(f64, Vec<usize>)>,
+    nodes: usize,
+}
+
+impl Search<'_> {
+    /// Lower bound of the cost of any selection with at least `input_count` inputs.
+    fn bound(&self, input_count: usize, remaining_count: usize) -> f64 {
+        let fee_rate = pool::sat_per_vb(self.fee_rate_per_kvb);
+        match self.objective {
+            Objective::Waste => {
+                let input_waste = SEGWIT_V1_TXIN_WEIGHT as f64 * (fee_rate - self.pool.long_term_feerate as f64);
+                input_count as f64 * input_waste + remaining_count as f64 * input_waste.min(0.0)
+            },
+            Objective::LowestFee => {
+                (pool::base_weight(self.payments) + input_count as u32 * SEGWIT_V1_TXIN_WEIGHT) as f64 * fee_rate
+            },
+        }
+    }
+
+    /// Explores every selection extending the current one with candidates from `position` on.
+    fn explore(&mut self, position: usize, selected_value: u64) {
+        self.nodes += 1;
+
+        if let Some((fee, change)) = self.pool.transaction(self.selection.len(), selected_value, self.payments, self.fee_rate_per_kvb) {
+            let cost = self.objective.cost(
+                self.selection.len(),
+                self.payments.len(),
+                fee as i64,
+                change.is_some(),
+                self.fee_rate_per_kvb,
+                &self.pool.waste_params(),
+            );
+            if self.best.as_ref().is_none_or(|(best, _)| cost < *best) {
+                self.best = Some((cost, self.selection.clone()));
+            }
+        }
+
+        for next in position..self.order.len() {
+            if selected_value + self.remaining_value[next] < self.amount {
+                break;
+            }
+            let bound = self.bound(self.selection.len() + 1, self.order.len() - next - 1);
+            if self.best.as_ref().is_some_and(|(best, _)| bound >= *best) {
+                break;
+            }
+
+            let index = self.order[next];
+            self.selection.push(index);
+            self.explore(next + 1, selected_value + self.pool.values()[index]);
+            self.selection.pop();
+        }
+    }
+}
+
+/// A reference selector finding the selection with the lowest cost for the given [`Objective`]
+/// through an exhaustive search, pruning the branches which can't beat the best selection found.
+///
+/// The search is exponential in the number of UTXOs, so pools bigger than `max_utxos` are
+/// funded by spending the largest coins first, recorded as `largest_first` instead of `optimal`.
+/// The transactions are built as in [`CandidatePool`].
+pub struct OptimalCoinSelect {
+    pool: CandidatePool,
+    max_utxos: usize,
+    objective: Objective,
+    last_search: Option<(usize, Option<f64>)>,
+}
+
+impl OptimalCoinSelect {
+    pub fn new(max_utxos: usize, objective: Objective, long_term_feerate: f32, dust_limit: u64, input_drain_weight: u32, output_drain_weight: u32) -> Self {
+        OptimalCoinSelect {
+            pool: CandidatePool::new(long_term_feerate, dust_limit, input_drain_weight, output_drain_weight),
+            max_utxos,
+            objective,
+            last_search: None,
+        }
+    }
+
+    fn largest_first(&self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Option<Vec<usize>> {
+        let values = self.pool.values();
+        let mut order = (0..values.len()).collect::<Vec<usize>>();
+        order.sort_by_key(|index| Reverse(values[*index]));
+
+        let mut selected_value = 0;
+        for (count, index) in order.iter().enumerate() {
+            selected_value += values[*index];
+            if self.pool.transaction(count + 1, selected_value, payments, fee_rate_per_kvb).is_some() {
+                return Some(order[..=count].to_vec());
+            }
+        }
+
+        None
+    }
+}
+
+impl TargetSelector for OptimalCoinSelect {
+    fn values(&self) -> Result<Vec<u64>, Box<dyn Error>> {
+        Ok(self.pool.values().to_vec())
+    }
+
+    fn deposit(&mut self, deposit: ScenarioEntry) -> Result<(), Box<dyn Error>> {
+        self.pool.push(Amount::from_btc(deposit.amount)?.to_sat());
+        Ok(())
+    }
+
+    fn load(&mut self, values: &[u64]) -> Result<(), Box<dyn Error>> {
+        self.pool.load(values);
+        Ok(())
+    }
+
+    fn statistics(&self) -> Vec<(String, String)> {
+        match self.last_search {
+            Some((nodes, cost)) => vec![
+                (String::from("optimal_nodes"), nodes.to_string()),
+                (String::from("optimal_cost"), cost.map_or(String::from("none"), |x| x.to_string())),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Result<SimulationEntry, Box<dyn Error>> {
+        self.last_search = None;
+
+        if self.pool.values().len() > self.max_utxos {
+            return match self.largest_first(payments, fee_rate_per_kvb) {
+                Some(selection) => self.pool.apply(&selection, payments, fee_rate_per_kvb, "largest_first"),
+                None => Ok(self.pool.entry(payments, fee_rate_per_kvb)),
+            };
+        }
+
+        let values = self.pool.values();
+        let mut order = (0..values.len()).collect::<Vec<usize>>();
+        order.sort_by_key(|index| Reverse(values[*index]));
+        let mut remaining_value = order.iter().rev().scan(0, |sum, index| {
+            *sum += values[*index];
+            Some(*sum)
+        }).collect::<Vec<u64>>();
+        remaining_value.reverse();
+
+        let mut search = Search {
+            pool: &self.pool,
+            payments,
+            fee_rate_per_kvb,
+            objective: self.objective,
+            order,
+            remaining_value,
+            amount: payments.iter().map(|x| x.amount).sum(),
+            selection: Vec::new(),
+            best: None,
+            nodes: 0,
+        };
+        search.explore(0, 0);
+
+        let (nodes, best) = (search.nodes, search.best);
+        self.last_search = Some((nodes, best.as_ref().map(|(cost, _)| *cost)));
+
+        match best {
+            Some((_, selection)) => self.pool.apply(&selection, payments, fee_rate_per_kvb, "optimal"),
+            None => Ok(self.pool.entry(payments, fee_rate_per_kvb)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SEGWIT_V1_TXOUT_WEIGHT;
+    use crate::stats::SplitMix64;
+
+    const LONG_TERM_FEERATE: f32 = 10.0;
+
+    fn selector(max_utxos: usize, objective: Objective, values: &[u64]) -> OptimalCoinSelect {
+        let mut selector = OptimalCoinSelect::new(max_utxos, objective, LONG_TERM_FEERATE, 294, SEGWIT_V1_TXIN_WEIGHT, SEGWIT_V1_TXOUT_WEIGHT);
+        selector.load(values).unwrap();
+        selector
+    }
+
+    /// The lowest cost of any selection of `values` funding `payments`, trying all of them.
+    fn brute_force(objective: Objective, values: &[u64], payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Option<f64> {
+        let pool = selector(values.len(), objective, values).pool;
+        (0..1usize << values.len())
+            .filter_map(|mask| {
+                let selection = (0..values.len()).filter(|i| mask & (1 << i) != 0).collect::<Vec<usize>>();
+                let selected_value = selection.iter().map(|i| values[*i]).sum();
+                let (fee, change) = pool.transaction(selection.len(), selected_value, payments, fee_rate_per_kvb)?;
+                Some(objective.cost(selection.len(), payments.len(), fee as i64, change.is_some(), fee_rate_per_kvb, &pool.waste_params()))
+            })
+            .min_by(f64::total_cmp)
+    }
+
+    #[test]
+    fn finds_the_lowest_cost_of_small_pools() {
+        let mut rng = SplitMix64::new(7);
+        for objective in [Objective::Waste, Objective::LowestFee] {
+            // Under, at and over the long term feerate, where the waste of inputs changes sign.
+            for fee_rate_per_kvb in [2e-5, 1e-4, 4e-4] {
+                for size in (1..=9).cycle().take(90) {
+                    let values = (0..size).map(|_| 1_000 + rng.below(50_000) as u64).collect::<Vec<u64>>();
+                    // Paying about the value of some of the UTxOs, selections without change
+                    // compete with the ones with change.
+                    let subset = values.iter().filter(|_| rng.below(2) == 0).sum::<u64>();
+                    let payments = [PendingPayment {
+                        amount: subset.saturating_sub(rng.below(5_000) as u64).max(500),
+                        weight: SEGWIT_V1_TXOUT_WEIGHT,
+                        ..Default::default()
+                    }];
+
+                    let mut selector = selector(size, objective, &values);
+                    let entry = selector.withdraw(&payments, fee_rate_per_kvb).unwrap();
+                    let cost = selector.last_search.and_then(|(_, cost)| cost);
+                    match brute_force(objective, &values, &payments, fee_rate_per_kvb) {
+                        Some(best) => {
+                            assert_eq!(entry.algorithm, "optimal");
+                            assert!((cost.unwrap() - best).abs() < 1e-6, "{:?} of {:?}: {:?} instead of {}", objective, values, cost, best);
+                        },
+                        None => assert_eq!(entry.algorithm, "failed"),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spends_the_largest_coins_of_big_pools() {
+        let payments = [PendingPayment {
+            amount: 50_000,
+            weight: SEGWIT_V1_TXOUT_WEIGHT,
+            ..Default::default()
+        }];
+        let mut selector = selector(3, Objective::Waste, &[10_000, 40_000, 30_000, 20_000]);
+        let entry = selector.withdraw(&payments, 1e-4).unwrap();
+        assert_eq!(entry.algorithm, "largest_first");
+        assert_eq!(entry.inputs, vec![40_000, 30_000]);
+    }
+}
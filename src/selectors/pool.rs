@@ -0,0 +1,255 @@
+use crate::{ SEGWIT_V1_TXIN_WEIGHT, SEGWIT_V1_TXOUT_WEIGHT };
+use crate::models::{ PendingPayment, SimulationEntry };
+
+use std::{
+    collections::HashSet,
+    error::Error,
+};
+
+pub fn varint_size(v: usize) -> u32 {
+    if v <= 0xfc {
+        return 1;
+    }
+    if v <= 0xffff {
+        return 3;
+    }
+    if v <= 0xffff_ffff {
+        return 5;
+    }
+    9
+}
+
+/// Converts the scenario feerates, in BTC per kvB, to sat/vB.
+pub fn sat_per_vb(fee_rate_per_kvb: f32) -> f64 {
+    fee_rate_per_kvb as f64 * 1e5
+}
+
+/// The size of a transaction paying `payments` without counting its inputs.
+pub fn base_weight(payments: &[PendingPayment]) -> u32 {
+    4 /* nVersion */
+        + 4 /* nLockTime */
+        + varint_size(0) /* inputs varint */
+        + varint_size(payments.len()) /* outputs varint */
+        + payments.iter().map(|x| x.weight).sum::<u32>()
+}
+
+/// What the `values` can pay at `fee_rate_per_kvb`, spending all of them worth more than their
+/// input fee.
+pub fn funds(values: &[u64], fee_rate_per_kvb: f32) -> u64 {
+    let fee = |weight: u32| (weight as f64 * sat_per_vb(fee_rate_per_kvb)).ceil() as u64;
+    values
+        .iter()
+        .filter_map(|value| value.checked_sub(fee(SEGWIT_V1_TXIN_WEIGHT)))
+        .sum::<u64>()
+        .saturating_sub(fee(base_weight(&[])))
+}
+
+/// The amount of `payment` and the fee of its output at `fee_rate_per_kvb`.
+pub fn payment_cost(payment: &PendingPayment, fee_rate_per_kvb: f32) -> u64 {
+    payment.amount + (payment.weight as f64 * sat_per_vb(fee_rate_per_kvb)).ceil() as u64
+}
+
+/// The feerate and weights waste is measured with, the ones given to the backends.
+#[derive(Debug, Clone, Copy)]
+pub struct WasteParams {
+    pub long_term_feerate: f32,
+    /// Size of the input spending a change output.
+    pub input_drain_weight: u32,
+    /// Size of a change output.
+    pub output_drain_weight: u32,
+}
+
+/// Waste of the transaction recorded in `entry`, or `None` if the selection failed.
+///
+/// Backends measure waste in their own units, so it's recomputed here with the same weights for
+/// all of them to be comparable.
+pub fn waste(entry: &SimulationEntry, fee_rate_per_kvb: f32, params: &WasteParams) -> Option<f64> {
+    waste_breakdown(entry, fee_rate_per_kvb, params).map(|(inputs, change)| inputs + change)
+}
+
+/// The [`waste`] of the inputs spent now instead of at the long term feerate, and the waste of
+/// either creating and later spending the change output or giving the excess away as fees.
+pub fn waste_breakdown(entry: &SimulationEntry, fee_rate_per_kvb: f32, params: &WasteParams) -> Option<(f64, f64)> {
+    let fee = entry.fee?;
+    if entry.algorithm == "failed" {
+        return None;
+    }
+
+    Some(transaction_waste(
+        entry.inputs.len(),
+        entry.output_count.unwrap_or(1),
+        fee,
+        entry.change_amount.is_some(),
+        fee_rate_per_kvb,
+        params,
+    ))
+}
+
+/// The [`waste_breakdown`] of a transaction spending `input_count` inputs and paying `fee`. When
+/// there is no change, `output_count` payments are assumed.
+pub fn transaction_waste(input_count: usize, output_count: usize, fee: i64, has_change: bool, fee_rate_per_kvb: f32, params: &WasteParams) -> (f64, f64) {
+    let fee_rate = sat_per_vb(fee_rate_per_kvb);
+    let long_term_feerate = params.long_term_feerate as f64;
+    let input_waste = input_count as f64 * SEGWIT_V1_TXIN_WEIGHT as f64 * (fee_rate - long_term_feerate);
+    let change_waste = if has_change {
+        params.output_drain_weight as f64 * fee_rate + params.input_drain_weight as f64 * long_term_feerate
+    } else {
+        // Without change the excess is given away on top of the fee the transaction needed.
+        let weight = 4 + 4 + varint_size(0) + varint_size(output_count)
+            + output_count as u32 * SEGWIT_V1_TXOUT_WEIGHT
+            + input_count as u32 * SEGWIT_V1_TXIN_WEIGHT;
+        f64::max(fee as f64 - weight as f64 * fee_rate, 0.0)
+    };
+
+    (input_waste, change_waste)
+}
+
+/// The wallet UTXOs of the selectors which only decide what coins to spend, leaving the
+/// transaction accounting to the simulator.
+///
+/// As in the Python selector, weights are sizes in vbytes and fees are paid in sat/vB.
+#[derive(Debug, Clone, Default)]
+pub struct CandidatePool {
+    values: Vec<u64>,
+    pub long_term_feerate: f32,
+    pub dust_limit: u64,
+    /// Size of the input spending a change output.
+    pub input_drain_weight: u32,
+    /// Size of a change output.
+    pub output_drain_weight: u32,
+}
+
+impl CandidatePool {
+    pub fn new(long_term_feerate: f32, dust_limit: u64, input_drain_weight: u32, output_drain_weight: u32) -> Self {
+        CandidatePool {
+            values: Vec::default(),
+            long_term_feerate,
+            dust_limit,
+            input_drain_weight,
+            output_drain_weight,
+        }
+    }
+
+    pub fn values(&self) -> &[u64] {
+        &self.values
+    }
+
+    /// The parameters the waste of the transactions built from this pool is measured with.
+    pub fn waste_params(&self) -> WasteParams {
+        WasteParams {
+            long_term_feerate: self.long_term_feerate,
+            input_drain_weight: self.input_drain_weight,
+            output_drain_weight: self.output_drain_weight,
+        }
+    }
+
+    pub fn push(&mut self, value: u64) {
+        self.values.push(value);
+    }
+
+    pub fn load(&mut self, values: &[u64]) {
+        self.values = values.to_vec();
+    }
+
+    pub fn balance(&self) -> u64 {
+        self.values.iter().sum::<u64>()
+    }
+
+    pub fn cost_to_empty_at_long_term_feerate(&self) -> f32 {
+        self.values.len() as f32 * SEGWIT_V1_TXIN_WEIGHT as f32 * self.long_term_feerate
+    }
+
+    /// The cost of creating a change output now and spending it in the future.
+    pub fn cost_of_change(&self, fee_rate: f64) -> f64 {
+        self.output_drain_weight as f64 * fee_rate + self.input_drain_weight as f64 * self.long_term_feerate as f64
+    }
+
+    /// The record of a withdrawal from the current pool, failed until a selection is applied.
+    pub fn entry(&self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> SimulationEntry {
+        SimulationEntry {
+            amount: payments.iter().map(|x| x.amount).sum(),
+            target_feerate: sat_per_vb(fee_rate_per_kvb) as f32,
+            algorithm: String::from("failed"),
+            utxo_count_before_payment: self.values.len(),
+            utxo_count_after_payment: self.values.len(),
+            cost_to_empty_at_long_term_feerate: self.cost_to_empty_at_long_term_feerate(),
+            balance: self.balance(),
+            ..Default::default()
+        }
+    }
+
+    /// The fee paid and the change created by a transaction spending `input_count` candidates
+    /// worth `selected_value` to pay `payments`, or `None` if they aren't enough.
+    ///
+    /// A change output is added when it's above the dust limit and cheaper than giving the excess
+    /// away as fees.
+    pub fn transaction(&self, input_count: usize, selected_value: u64, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Option<(u64, Option<u64>)> {
+        let fee_rate = sat_per_vb(fee_rate_per_kvb);
+        let amount = payments.iter().map(|x| x.amount).sum::<u64>();
+        let weight = base_weight(payments) + input_count as u32 * SEGWIT_V1_TXIN_WEIGHT;
+        let fee = (weight as f64 * fee_rate).ceil() as u64;
+        let excess = selected_value.checked_sub(amount + fee)?;
+
+        let change_fee = (self.output_drain_weight as f64 * fee_rate).ceil() as u64;
+        let change = excess
+            .checked_sub(change_fee)
+            .filter(|change| *change >= self.dust_limit && excess as f64 > self.cost_of_change(fee_rate));
+
+        match change {
+            Some(change) => Some((fee + change_fee, Some(change))),
+            None => Some((selected_value - amount, None)),
+        }
+    }
+
+    /// Builds the [`CandidatePool::transaction`] spending the candidates at the `selection`
+    /// indices to pay `payments`, removing them from the pool and pushing back the change.
+    pub fn apply(&mut self, selection: &[usize], payments: &[PendingPayment], fee_rate_per_kvb: f32, algorithm: &str) -> Result<SimulationEntry, Box<dyn Error>> {
+        let mut withdraw = self.entry(payments, fee_rate_per_kvb);
+
+        let selection: HashSet<usize> = HashSet::from_iter(selection.iter().cloned());
+        if let Some(index) = selection.iter().find(|x| **x >= self.values.len()) {
+            return Err(format!("selected candidate {} but there are only {}", index, self.values.len()).into());
+        }
+
+        let fee_rate = sat_per_vb(fee_rate_per_kvb);
+        let inputs = self.values
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| selection.contains(index))
+            .map(|(_, value)| *value)
+            .collect::<Vec<u64>>();
+        let selected_value = inputs.iter().sum::<u64>();
+
+        let (fee, change) = self
+            .transaction(inputs.len(), selected_value, payments, fee_rate_per_kvb)
+            .ok_or_else(|| format!("selection of {} sats doesn't pay {} sats plus fees", selected_value, withdraw.amount))?;
+
+        let weight = base_weight(payments) + inputs.len() as u32 * SEGWIT_V1_TXIN_WEIGHT;
+        let (weight, change_waste) = match change {
+            Some(_) => (weight + self.output_drain_weight, self.cost_of_change(fee_rate)),
+            None => (weight, (fee as f64 - (weight as f64 * fee_rate).ceil()).max(0.0)),
+        };
+
+        let mut index = 0;
+        self.values.retain(|_| {
+            index += 1;
+            !selection.contains(&(index - 1))
+        });
+        if let Some(change) = change {
+            self.values.push(change);
+        }
+
+        let input_waste = inputs.len() as f64 * SEGWIT_V1_TXIN_WEIGHT as f64 * (fee_rate - self.long_term_feerate as f64);
+        withdraw.algorithm = algorithm.to_string();
+        withdraw.negative_effective_valued_inputs = Some(inputs.iter().filter(|x| (**x as f64) < SEGWIT_V1_TXIN_WEIGHT as f64 * fee_rate).count());
+        withdraw.fee = Some(fee as i64);
+        withdraw.real_feerate = Some((fee as f64 / weight as f64) as f32);
+        withdraw.output_count = Some(payments.len() + change.map_or(0, |_| 1));
+        withdraw.change_amount = change;
+        withdraw.utxo_count_after_payment = self.values.len();
+        withdraw.waste_score = Some((input_waste + change_waste) as f32);
+        withdraw.inputs = inputs;
+
+        Ok(withdraw)
+    }
+}
@@ -1,24 +1,102 @@
+use crate::SEGWIT_V1_TXIN_WEIGHT;
 use crate::selectors::TargetSelector;
+use crate::selectors::worker::{ RemoteError, Worker };
 use crate::models::{ PendingPayment, ScenarioEntry, SimulationEntry };
 
+#[cfg(feature = "python")]
 use pyo3::prelude::{Python, PyModule, PyObject, PyAnyMethods };
+#[cfg(feature = "python")]
 use pyo3::types::IntoPyDict;
 
+use bitcoin::amount::Amount;
+use serde_json::{ json, Value };
+use tracing::{ Level, event };
+
+use std::cell::RefCell;
 use std::error::Error;
+#[cfg(feature = "python")]
 use std::collections::HashMap;
+use std::process::Command;
+use std::time::Duration;
+
+const PYTHON_COIN_SELECT: &str = include_str!("./python_coin_select.py");
+const PYTHON_WORKER: &str = include_str!("./python_worker.py");
+
+enum Backend {
+    #[cfg(feature = "python")]
+    Embedded(PyObject),
+    // Requests mutate the worker pipes, even the read only ones.
+    Worker(RefCell<IsolatedWorker>),
+}
+
+/// How the worker process is started, to start it again when it hangs or crashes.
+struct WorkerSetup {
+    interpreter: String,
+    timeout: Duration,
+    init: Value,
+}
+
+impl WorkerSetup {
+    fn spawn(&self) -> Result<Worker, Box<dyn Error>> {
+        let mut worker = Worker::spawn(
+            Command::new(&self.interpreter).args(["-u", "-c", PYTHON_WORKER, PYTHON_COIN_SELECT]),
+            self.timeout,
+        )?;
+        worker.call("init", self.init.clone())?;
+        Ok(worker)
+    }
+}
+
+/// A worker process and the wallet it was last known to hold, kept up to date with the requests
+/// so it can be given to a new worker when this one hangs or crashes.
+struct IsolatedWorker {
+    worker: Worker,
+    setup: WorkerSetup,
+    values: Vec<u64>,
+}
+
+impl IsolatedWorker {
+    /// Calls `method` of the worker. When it hangs or crashes rather than raising, a new worker
+    /// holding the last known wallet replaces it before the error is returned.
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, Box<dyn Error>> {
+        let result = self.worker.call(method, params);
+        if let Err(err) = &result {
+            if !err.is::<RemoteError>() {
+                event!(Level::ERROR, "python worker failed, restarting it: {}", err);
+                self.worker = self.setup.spawn()?;
+                self.worker.call("load", json!({ "values": self.values }))?;
+            }
+        }
+        result
+    }
+}
 
+/// The record of a withdrawal of `payments` which found no selection in a wallet of `values`.
+fn failed(payments: &[PendingPayment], fee_rate_per_kvb: f32, values: &[u64], long_term_feerate: f32) -> SimulationEntry {
+    SimulationEntry {
+        amount: payments.iter().map(|x| x.amount).sum(),
+        target_feerate: fee_rate_per_kvb * 1e5,
+        algorithm: String::from("failed"),
+        utxo_count_before_payment: values.len(),
+        utxo_count_after_payment: values.len(),
+        cost_to_empty_at_long_term_feerate: values.len() as f32 * SEGWIT_V1_TXIN_WEIGHT as f32 * long_term_feerate,
+        balance: values.iter().sum(),
+        ..Default::default()
+    }
+}
 
 pub struct PythonCoinSelect {
-    py_selector: PyObject,
+    backend: Backend,
+    long_term_feerate: f32,
 }
 
 impl PythonCoinSelect {
-    pub fn new(long_term_feerate: f32, dust_limit: u64, input_drain_weight: u32, output_drain_weight: u32) -> Result<Self, Box<dyn Error>> {
+    #[cfg(feature = "python")]
+    pub fn new(long_term_feerate: f32, dust_limit: u64, input_drain_weight: u32, output_drain_weight: u32, seed: Option<u64>) -> Result<Self, Box<dyn Error>> {
         Python::with_gil(|py| {
-            let code = include_str!("./python_coin_select.py");
             let python_coin_selector: PyObject = PyModule::from_code_bound(
                 py,
-                code,
+                PYTHON_COIN_SELECT,
                 "python_coin_select.py",
                 "python_coin_select"
             )?
@@ -26,63 +104,191 @@ impl PythonCoinSelect {
             .into();
 
             Ok(PythonCoinSelect {
-                py_selector: python_coin_selector.call1(py, (long_term_feerate, dust_limit, input_drain_weight, output_drain_weight))?,
+                backend: Backend::Embedded(python_coin_selector.call1(py, (long_term_feerate, dust_limit, input_drain_weight, output_drain_weight, seed))?),
+                long_term_feerate,
             })
         })
     }
+
+    /// Runs the selector in a child `interpreter` process instead of the embedded one.
+    ///
+    /// A withdrawal taking longer than `timeout`, or crashing the worker, is accounted as a failed
+    /// selection, and a new worker is started with the same wallet, so a hanging or crashing
+    /// algorithm doesn't take the simulator down with it. Deposits, loads and wallet queries are
+    /// handed over to the new worker as well.
+    pub fn new_isolated(
+        long_term_feerate: f32,
+        dust_limit: u64,
+        input_drain_weight: u32,
+        output_drain_weight: u32,
+        seed: Option<u64>,
+        interpreter: &str,
+        timeout: Duration,
+    ) -> Result<Self, Box<dyn Error>> {
+        let setup = WorkerSetup {
+            interpreter: interpreter.to_string(),
+            timeout,
+            init: json!({
+                "long_term_feerate": long_term_feerate,
+                "dust_limit": dust_limit,
+                "input_drain_weight": input_drain_weight,
+                "output_drain_weight": output_drain_weight,
+                "seed": seed,
+            }),
+        };
+
+        Ok(PythonCoinSelect {
+            backend: Backend::Worker(RefCell::new(IsolatedWorker {
+                worker: setup.spawn()?,
+                setup,
+                values: Vec::new(),
+            })),
+            long_term_feerate,
+        })
+    }
 }
 
 
 impl TargetSelector for PythonCoinSelect {
-    fn values(&self) -> Vec<u64> {
-        Python::with_gil(|py| {
-            self.py_selector
-                .bind(py)
-                .call_method("values", (), None)
-                .unwrap()
-                .extract()
-                .unwrap()
-        })
+    fn values(&self) -> Result<Vec<u64>, Box<dyn Error>> {
+        match &self.backend {
+            #[cfg(feature = "python")]
+            Backend::Embedded(py_selector) => Python::with_gil(|py| {
+                Ok(py_selector
+                    .bind(py)
+                    .call_method("values", (), None)?
+                    .extract()?)
+            }),
+            Backend::Worker(worker) => {
+                let mut worker = worker.borrow_mut();
+                match worker.call("values", json!({})) {
+                    Ok(values) => {
+                        worker.values = serde_json::from_value(values)?;
+                        Ok(worker.values.clone())
+                    },
+                    Err(err) if err.is::<RemoteError>() => Err(err),
+                    // The new worker holds the wallet this one was last known to hold.
+                    Err(_) => Ok(worker.values.clone()),
+                }
+            },
+        }
     }
 
     fn deposit(&mut self, record: ScenarioEntry) -> Result<(), Box<dyn Error>> {
-        let mut kwargs = HashMap::<&str, ScenarioEntry>::new();
-        kwargs.insert("scenario_entry", record);
-        Python::with_gil(|py| {
-            self.py_selector
-                .bind(py)
-                .call_method("deposit", (), Some(&kwargs.into_py_dict_bound(py)))
-                .unwrap();
-        });
-        Ok(())
+        match &mut self.backend {
+            #[cfg(feature = "python")]
+            Backend::Embedded(py_selector) => {
+                let mut kwargs = HashMap::<&str, ScenarioEntry>::new();
+                kwargs.insert("scenario_entry", record);
+                Python::with_gil(|py| {
+                    py_selector
+                        .bind(py)
+                        .call_method("deposit", (), Some(&kwargs.into_py_dict_bound(py)))?;
+                    Ok(())
+                })
+            },
+            Backend::Worker(worker) => {
+                let worker = worker.get_mut();
+                let value = Amount::from_btc(record.amount)?.to_sat();
+                let params = json!({ "scenario_entry": record });
+                match worker.call("deposit", params.clone()) {
+                    Ok(_) => {},
+                    Err(err) if err.is::<RemoteError>() => return Err(err),
+                    // The new worker holds the wallet from before the deposit.
+                    Err(_) => {
+                        worker.worker.call("deposit", params)?;
+                    },
+                }
+                worker.values.push(value);
+                Ok(())
+            },
+        }
     }
 
-    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> SimulationEntry {
-        Python::with_gil(|py| {
-            let rust_dict: HashMap<String, PyObject> = self.py_selector
-                .bind(py)
-                .call_method("withdraw", (payments.to_vec(), fee_rate_per_kvb), None)
-                .unwrap()
-                .extract()
-                .unwrap();
-
-            SimulationEntry {
-                id: rust_dict.get("id").unwrap().extract(py).unwrap(),
-                inputs: rust_dict.get("inputs").unwrap().extract(py).unwrap(),
-                amount: rust_dict.get("amount").unwrap().extract(py).unwrap(),
-                fee: rust_dict.get("fee").unwrap().extract(py).unwrap(),
-                target_feerate: rust_dict.get("target_feerate").unwrap().extract(py).unwrap(),
-                real_feerate: rust_dict.get("real_feerate").unwrap().extract(py).unwrap(),
-                algorithm: rust_dict.get("algorithm").unwrap().extract(py).unwrap(),
-                negative_effective_valued_inputs: rust_dict.get("negative_effective_valued_inputs").unwrap().extract(py).unwrap(),
-                output_count: rust_dict.get("output_count").unwrap().extract(py).unwrap(),
-                change_amount: rust_dict.get("change_amount").unwrap().extract(py).unwrap(),
-                utxo_count_before_payment: rust_dict.get("utxo_count_before_payment").unwrap().extract(py).unwrap(),
-                utxo_count_after_payment: rust_dict.get("utxo_count_after_payment").unwrap().extract(py).unwrap(),
-                cost_to_empty_at_long_term_feerate: rust_dict.get("cost_to_empty_at_long_term_feerate").unwrap().extract(py).unwrap(),
-                balance: rust_dict.get("balance").unwrap().extract(py).unwrap(),
-                waste_score: rust_dict.get("waste_score").unwrap().extract(py).unwrap(),
-            }
-        })
+    fn load(&mut self, values: &[u64]) -> Result<(), Box<dyn Error>> {
+        match &mut self.backend {
+            #[cfg(feature = "python")]
+            Backend::Embedded(py_selector) => Python::with_gil(|py| {
+                py_selector
+                    .bind(py)
+                    .call_method1("load", (values.to_vec(),))?;
+                Ok(())
+            }),
+            Backend::Worker(worker) => {
+                let worker = worker.get_mut();
+                worker.values = values.to_vec();
+                match worker.call("load", json!({ "values": values })) {
+                    Err(err) if err.is::<RemoteError>() => Err(err),
+                    // Either way the worker holds the `values` now.
+                    _ => Ok(()),
+                }
+            },
+        }
+    }
+
+    /// The knapsack and single random draw algorithms select at random.
+    fn randomized(&self) -> bool {
+        true
+    }
+
+    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Result<SimulationEntry, Box<dyn Error>> {
+        match &mut self.backend {
+            #[cfg(feature = "python")]
+            Backend::Embedded(py_selector) => Python::with_gil(|py| {
+                let rust_dict: HashMap<String, PyObject> = py_selector
+                    .bind(py)
+                    .call_method("withdraw", (payments.to_vec(), fee_rate_per_kvb), None)?
+                    .extract()?;
+
+                Ok(SimulationEntry {
+                    id: rust_dict.get("id").unwrap().extract(py)?,
+                    inputs: rust_dict.get("inputs").unwrap().extract(py)?,
+                    amount: rust_dict.get("amount").unwrap().extract(py)?,
+                    fee: rust_dict.get("fee").unwrap().extract(py)?,
+                    target_feerate: rust_dict.get("target_feerate").unwrap().extract(py)?,
+                    real_feerate: rust_dict.get("real_feerate").unwrap().extract(py)?,
+                    algorithm: rust_dict.get("algorithm").unwrap().extract(py)?,
+                    negative_effective_valued_inputs: rust_dict.get("negative_effective_valued_inputs").unwrap().extract(py)?,
+                    output_count: rust_dict.get("output_count").unwrap().extract(py)?,
+                    change_amount: rust_dict.get("change_amount").unwrap().extract(py)?,
+                    utxo_count_before_payment: rust_dict.get("utxo_count_before_payment").unwrap().extract(py)?,
+                    utxo_count_after_payment: rust_dict.get("utxo_count_after_payment").unwrap().extract(py)?,
+                    cost_to_empty_at_long_term_feerate: rust_dict.get("cost_to_empty_at_long_term_feerate").unwrap().extract(py)?,
+                    balance: rust_dict.get("balance").unwrap().extract(py)?,
+                    waste_score: rust_dict.get("waste_score").unwrap().extract(py)?,
+                    ..Default::default()
+                })
+            }),
+            Backend::Worker(worker) => {
+                let worker = worker.get_mut();
+                match worker.call("withdraw", json!({ "payments": payments, "fee_rate_per_kvb": fee_rate_per_kvb })) {
+                    Ok(result) => {
+                        let entry: SimulationEntry = serde_json::from_value(result)?;
+                        // The worker puts the change first, ahead of the UTxOs left.
+                        let mut values = entry.change_amount.into_iter().collect::<Vec<u64>>();
+                        let mut inputs = entry.inputs.clone();
+                        for value in worker.values.iter() {
+                            match inputs.iter().position(|x| x == value) {
+                                Some(index) => {
+                                    inputs.swap_remove(index);
+                                },
+                                None => values.push(*value),
+                            }
+                        }
+                        worker.values = values;
+                        Ok(entry)
+                    },
+                    // The algorithm raised but the worker is still alive and its wallet untouched,
+                    // so the withdrawal is accounted as a failed selection.
+                    Err(err) if err.is::<RemoteError>() => {
+                        event!(Level::ERROR, "python selector failed: {}", err);
+                        Ok(failed(payments, fee_rate_per_kvb, &worker.values, self.long_term_feerate))
+                    },
+                    // The worker hung or crashed, and was replaced by a new one with the same
+                    // wallet, so the withdrawal is accounted as a failed selection as well.
+                    Err(_) => Ok(failed(payments, fee_rate_per_kvb, &worker.values, self.long_term_feerate)),
+                }
+            },
+        }
     }
 }
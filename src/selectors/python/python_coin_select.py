@@ -21,6 +21,9 @@ from bitcoin_coin_selection.selection_types.coin_selection_params import (
 
 from bitcoinlib.values import Value
 
+import random
+from typing import Optional
+
 SEGWIT_V1_TXIN_WEIGHT = 68
 SEGWIT_V1_TXOUT_WEIGHT = 31
 
@@ -63,7 +66,11 @@ def select_coins(params: CoinSelectionParams) -> tuple[CoinSelection, str]:
 
 class PythonCoinSelector:
 
-    def __init__(self, long_term_feerate: float, dust_limit: int, input_drain_weight: int, output_drain_weight: int) -> None:
+    def __init__(self, long_term_feerate: float, dust_limit: int, input_drain_weight: int, output_drain_weight: int, seed: Optional[int] = None) -> None:
+        # Knapsack and single random draw rely on the global generator, seeding it makes the
+        # selections reproducible.
+        if seed is not None:
+            random.seed(seed)
         self.deposit_count = 0
         self.candidates: list[OutputGroup] = []
         self.long_term_feerate = long_term_feerate
@@ -84,6 +91,18 @@ class PythonCoinSelector:
         self.deposit_count += 1
         self.candidates.append(OutputGroup("", [input_coin]))
 
+    def load(self, values: list[int]) -> None:
+        self.candidates = []
+        for value in values:
+            input_coin = InputCoin(
+                tx_hash=str(self.deposit_count),
+                vout=0,
+                value=value,
+                input_bytes=SEGWIT_V1_TXIN_WEIGHT
+            )
+            self.deposit_count += 1
+            self.candidates.append(OutputGroup("", [input_coin]))
+
     def cost_to_empty_at_long_term_feerate(self) -> float:
         return sum((x.input_bytes for y in self.candidates for x in y.outputs)) * self.long_term_feerate
 
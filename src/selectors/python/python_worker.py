@@ -0,0 +1,42 @@
+import json
+import sys
+import traceback
+
+# The selector source is handed over as the first argument, so the worker doesn't depend on
+# where the simulator binary was built or installed.
+namespace: dict = {"__name__": "python_coin_select"}
+exec(compile(sys.argv[1], "python_coin_select.py", "exec"), namespace)
+
+# Keep stdout for the protocol alone, anything printed by the selection algorithms goes to stderr.
+protocol = sys.stdout
+sys.stdout = sys.stderr
+
+selector = None
+
+for line in sys.stdin:
+    request = json.loads(line)
+    method = request["method"]
+    params = request.get("params") or {}
+
+    try:
+        if method == "init":
+            selector = namespace["PythonCoinSelector"](**params)
+            result = None
+        elif method == "deposit":
+            selector.deposit(params["scenario_entry"])
+            result = None
+        elif method == "withdraw":
+            result = selector.withdraw(params["payments"], params["fee_rate_per_kvb"])
+        elif method == "values":
+            result = selector.values()
+        elif method == "load":
+            selector.load(params["values"])
+            result = None
+        else:
+            raise ValueError(f"unknown method {method}")
+        response = {"result": result}
+    except Exception:
+        response = {"error": traceback.format_exc()}
+
+    protocol.write(json.dumps(response) + "\n")
+    protocol.flush()
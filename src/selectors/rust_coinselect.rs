@@ -1,4 +1,5 @@
 use crate::selectors::TargetSelector;
+use crate::selectors::pool::varint_size;
 use crate::{ SEGWIT_V1_TXIN_WEIGHT, SEGWIT_V1_TXOUT_WEIGHT };
 use crate::models::{ PendingPayment, ScenarioEntry, SimulationEntry };
 
@@ -11,19 +12,6 @@ use bitcoin::amount::Amount;
 
 use rust_coinselect::{ OutputGroup, CoinSelectionOpt, ExcessStrategy, SelectionOutput, select_coin_fifo };
 
-fn varint_size(v: usize) -> u32 {
-    if v <= 0xfc {
-        return 1;
-    }
-    if v <= 0xffff {
-        return 3;
-    }
-    if v <= 0xffff_ffff {
-        return 5;
-    }
-    9
-}
-
 fn effective_value(value: u64, weight: u32, sats_per_wu: f32) -> f32 {
     value as f32 - (weight as f32 * sats_per_wu)
 }
@@ -55,14 +43,14 @@ impl RustCoinSelect {
     }
 
     fn balance(&self) -> u64 {
-        self.values().iter().sum::<u64>()
+        self.candidates.iter().map(|x| x.value).sum::<u64>()
     }
 }
 
 
 impl TargetSelector for RustCoinSelect {
-    fn values(&self) -> Vec<u64> {
-        self.candidates.iter().map(|x| x.value).collect::<Vec<u64>>()
+    fn values(&self) -> Result<Vec<u64>, Box<dyn Error>> {
+        Ok(self.candidates.iter().map(|x| x.value).collect::<Vec<u64>>())
     }
 
     fn deposit(&mut self, deposit: ScenarioEntry) -> Result<(), Box<dyn Error>> {
@@ -76,7 +64,23 @@ impl TargetSelector for RustCoinSelect {
         self.sequence_counter += 1;
         Ok(())
     }
-    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> SimulationEntry {
+
+    fn load(&mut self, values: &[u64]) -> Result<(), Box<dyn Error>> {
+        self.candidates.clear();
+        for value in values {
+            self.candidates.push(OutputGroup {
+                creation_sequence: Some(self.sequence_counter),
+                input_count: 1,
+                weight: SEGWIT_V1_TXIN_WEIGHT,
+                value: *value,
+                is_segwit: true
+            });
+            self.sequence_counter += 1;
+        }
+        Ok(())
+    }
+
+    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Result<SimulationEntry, Box<dyn Error>> {
         let (output_count, output_weight_total) = payments
             .iter()
             .map(|x| x.weight)
@@ -107,7 +111,8 @@ impl TargetSelector for RustCoinSelect {
 
         let mut withdraw: SimulationEntry = SimulationEntry {
             amount: selection_options.target_value,
-            target_feerate,
+            // Recorded in sat/vB as the other backends do.
+            target_feerate: fee_rate_per_kvb * 1e5,
             utxo_count_after_payment: self.candidates.len(),
             utxo_count_before_payment: self.candidates.len(),
             cost_to_empty_at_long_term_feerate: self.cost_to_empty_at_long_term_feerate(),
@@ -123,7 +128,7 @@ impl TargetSelector for RustCoinSelect {
             },
             Err(_) => {
                 withdraw.algorithm = String::from("failed");
-                return withdraw
+                return Ok(withdraw)
             },
         };
 
@@ -157,6 +162,6 @@ impl TargetSelector for RustCoinSelect {
         };
         withdraw.output_count = Some(selected_inputs.len() + 1);
         withdraw.utxo_count_after_payment = self.candidates.len();
-        withdraw
+        Ok(withdraw)
     }
 }
@@ -0,0 +1,154 @@
+use crate::SEGWIT_V1_TXIN_WEIGHT;
+use crate::selectors::TargetSelector;
+use crate::selectors::pool::{ self, CandidatePool };
+use crate::models::{ PendingPayment, ScenarioEntry, SimulationEntry };
+
+use std::{
+    error::Error,
+    path::PathBuf,
+};
+
+use bitcoin::amount::Amount;
+use rhai::{ Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST };
+use tracing::{ Level, event };
+
+/// A selector whose policy is a [Rhai](https://rhai.rs) script.
+///
+/// The script must define `select(candidates, target)`. `candidates` is an array of
+/// `#{ index, value, weight, effective_value }` maps and `target` a
+/// `#{ value, fee_rate, long_term_fee_rate, base_weight }` map, with feerates in sat/vB and
+/// weights in vbytes. It returns the array of selected candidate indices, or `()` when there is no
+/// selection.
+///
+/// A selection running for more than `max_operations`, failing or returning anything but distinct
+/// candidate indices is logged and accounted as failed. As in [`CandidatePool`], the simulator
+/// builds the transactions.
+pub struct ScriptCoinSelect {
+    engine: Engine,
+    ast: AST,
+    pool: CandidatePool,
+}
+
+impl ScriptCoinSelect {
+    pub fn new(script_path: &str, max_operations: u64, long_term_feerate: f32, dust_limit: u64, input_drain_weight: u32, output_drain_weight: u32) -> Result<Self, Box<dyn Error>> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(max_operations);
+        let ast = engine.compile_file(PathBuf::from(script_path))?;
+
+        Ok(ScriptCoinSelect {
+            engine,
+            ast,
+            pool: CandidatePool::new(long_term_feerate, dust_limit, input_drain_weight, output_drain_weight),
+        })
+    }
+}
+
+impl TargetSelector for ScriptCoinSelect {
+    fn values(&self) -> Result<Vec<u64>, Box<dyn Error>> {
+        Ok(self.pool.values().to_vec())
+    }
+
+    fn deposit(&mut self, deposit: ScenarioEntry) -> Result<(), Box<dyn Error>> {
+        self.pool.push(Amount::from_btc(deposit.amount)?.to_sat());
+        Ok(())
+    }
+
+    fn load(&mut self, values: &[u64]) -> Result<(), Box<dyn Error>> {
+        self.pool.load(values);
+        Ok(())
+    }
+
+    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Result<SimulationEntry, Box<dyn Error>> {
+        let fee_rate = pool::sat_per_vb(fee_rate_per_kvb);
+
+        let candidates = self.pool
+            .values()
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let mut candidate = Map::new();
+                candidate.insert("index".into(), Dynamic::from(index as i64));
+                candidate.insert("value".into(), Dynamic::from(*value as i64));
+                candidate.insert("weight".into(), Dynamic::from(SEGWIT_V1_TXIN_WEIGHT as i64));
+                candidate.insert("effective_value".into(), Dynamic::from(*value as f64 - SEGWIT_V1_TXIN_WEIGHT as f64 * fee_rate));
+                Dynamic::from_map(candidate)
+            })
+            .collect::<Array>();
+
+        let mut target = Map::new();
+        target.insert("value".into(), Dynamic::from(payments.iter().map(|x| x.amount).sum::<u64>() as i64));
+        target.insert("fee_rate".into(), Dynamic::from(fee_rate));
+        target.insert("long_term_fee_rate".into(), Dynamic::from(self.pool.long_term_feerate as f64));
+        target.insert("base_weight".into(), Dynamic::from(pool::base_weight(payments) as i64));
+
+        // The pool is left untouched by a faulty script, so the simulation goes on.
+        let result = match self.engine.call_fn::<Dynamic>(&mut Scope::new(), &self.ast, "select", (candidates, Dynamic::from_map(target))) {
+            Ok(result) => result,
+            Err(err) if matches!(*err, EvalAltResult::ErrorTooManyOperations(_)) => {
+                event!(Level::INFO, "script selector ran out of operations");
+                return Ok(self.pool.entry(payments, fee_rate_per_kvb));
+            },
+            Err(err) => {
+                event!(Level::ERROR, "script selector failed: {}", err);
+                return Ok(self.pool.entry(payments, fee_rate_per_kvb));
+            },
+        };
+
+        if result.is_unit() {
+            return Ok(self.pool.entry(payments, fee_rate_per_kvb));
+        }
+
+        let selection = match selection(result, self.pool.values().len()) {
+            Ok(selection) => selection,
+            Err(err) => {
+                event!(Level::ERROR, "script selector made an invalid selection: {}", err);
+                return Ok(self.pool.entry(payments, fee_rate_per_kvb));
+            },
+        };
+
+        match self.pool.apply(&selection, payments, fee_rate_per_kvb, "script") {
+            Ok(entry) => Ok(entry),
+            Err(err) => {
+                event!(Level::ERROR, "script selector made an invalid selection: {}", err);
+                Ok(self.pool.entry(payments, fee_rate_per_kvb))
+            },
+        }
+    }
+}
+
+/// The distinct candidate indices, out of `count` candidates, returned by the script.
+fn selection(result: Dynamic, count: usize) -> Result<Vec<usize>, String> {
+    let mut selection = Vec::new();
+    for element in result.try_cast::<Array>().ok_or("`select` must return an array of candidate indices or ()")? {
+        let index = element.as_int().map_err(|kind| format!("selected a {} instead of an index", kind))?;
+        let index = usize::try_from(index)
+            .ok()
+            .filter(|index| *index < count)
+            .ok_or_else(|| format!("selected candidate {} but there are only {}", index, count))?;
+        if selection.contains(&index) {
+            return Err(format!("selected candidate {} twice", index));
+        }
+        selection.push(index);
+    }
+    Ok(selection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn array(elements: Vec<Dynamic>) -> Dynamic {
+        Dynamic::from_array(elements)
+    }
+
+    #[test]
+    fn selections_are_distinct_candidate_indices() {
+        assert_eq!(selection(array(vec![Dynamic::from(2_i64), Dynamic::from(0_i64)]), 3), Ok(vec![2, 0]));
+
+        assert!(selection(Dynamic::from(1_i64), 3).is_err());
+        assert!(selection(array(vec![Dynamic::from(1.5)]), 3).is_err());
+        assert!(selection(array(vec![Dynamic::from(3_i64)]), 3).is_err());
+        assert!(selection(array(vec![Dynamic::from(-1_i64)]), 3).is_err());
+        assert!(selection(array(vec![Dynamic::from(1_i64), Dynamic::from(1_i64)]), 3).is_err());
+    }
+}
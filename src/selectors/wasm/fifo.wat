@@ -0,0 +1,45 @@
+;; Example plugin for the `wasm` backend: spends the candidates in the order they arrived until
+;; the payments and the fees are covered.
+;;
+;; Build it with `wat2wasm fifo.wat`, or load the text file directly as wasmtime understands both.
+(module
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  ;; Bump allocator, the host only allocates when it needs a bigger buffer.
+  (func (export "alloc") (param $size i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (global.set $heap (i32.add (local.get $ptr) (local.get $size)))
+    (block $enough
+      (br_if $enough
+        (i32.le_u (global.get $heap) (i32.mul (memory.size) (i32.const 65536))))
+      (drop (memory.grow
+        (i32.add (i32.div_u (local.get $size) (i32.const 65536)) (i32.const 1)))))
+    (local.get $ptr))
+
+  (func (export "select")
+    (param $candidates i32) (param $count i32) (param $target i64)
+    (param $fee_rate f64) (param $long_term_fee_rate f64)
+    (param $base_weight i32) (param $input_weight i32) (param $out i32)
+    (result i32)
+    (local $i i32) (local $value i64) (local $weight i32)
+    (local.set $weight (local.get $base_weight))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $count)))
+        (i32.store
+          (i32.add (local.get $out) (i32.shl (local.get $i) (i32.const 2)))
+          (local.get $i))
+        (local.set $value
+          (i64.add (local.get $value)
+            (i64.load (i32.add (local.get $candidates) (i32.shl (local.get $i) (i32.const 3))))))
+        (local.set $weight (i32.add (local.get $weight) (local.get $input_weight)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (if (i64.ge_u (local.get $value)
+              (i64.add (local.get $target)
+                (i64.trunc_f64_u
+                  (f64.ceil (f64.mul (f64.convert_i32_u (local.get $weight)) (local.get $fee_rate))))))
+          (then (return (local.get $i))))
+        (br $next)))
+    (i32.const -1)))
@@ -0,0 +1,185 @@
+use crate::SEGWIT_V1_TXIN_WEIGHT;
+use crate::selectors::TargetSelector;
+use crate::selectors::pool::{ self, CandidatePool };
+use crate::models::{ PendingPayment, ScenarioEntry, SimulationEntry };
+
+use std::{
+    cmp,
+    error::Error,
+};
+
+use bitcoin::amount::Amount;
+use tracing::{ Level, event };
+use wasmtime::{ Config, Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc };
+
+type SelectParams = (u32, u32, u64, f64, f64, u32, u32, u32);
+
+/// A selector compiled to WebAssembly, run in a sandbox without access to the host.
+///
+/// The module must not import anything and must export:
+/// - `memory`: the memory shared with the simulator.
+/// - `alloc(size: u32) -> u32`: the address of `size` free bytes, which the simulator keeps
+///   reusing until it needs a bigger buffer.
+/// - `select(candidates: u32, count: u32, target: u64, fee_rate: f64, long_term_fee_rate: f64,
+///   base_weight: u32, input_weight: u32, out: u32) -> i32`: given `count` candidate values as
+///   little endian `u64` at `candidates`, writes the indices of the selected ones as little endian
+///   `u32` at `out` and returns how many were selected, or a negative number when no selection
+///   was found. Feerates are in sat/vB and weights in vbytes.
+///
+/// Each selection is given `fuel` units of execution and the module at most `max_memory` bytes
+/// of memory. A selection running out of fuel, trapping or returning an invalid selection is
+/// accounted as failed, and the module is instantiated again after a trap as its memory may be
+/// left inconsistent. As in [`CandidatePool`], the simulator builds the transactions.
+pub struct WasmCoinSelect {
+    module: Module,
+    sandbox: Sandbox,
+    fuel: u64,
+    max_memory: usize,
+    pool: CandidatePool,
+}
+
+/// An instance of the module with its own store.
+struct Sandbox {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<u32, u32>,
+    select: TypedFunc<SelectParams, i32>,
+    // Address and capacity, in candidates, of the buffer shared with the module.
+    buffer: Option<(u32, usize)>,
+}
+
+impl Sandbox {
+    fn new(module: &Module, max_memory: usize) -> Result<Self, Box<dyn Error>> {
+        let limits = StoreLimitsBuilder::new().memory_size(max_memory).build();
+        let mut store = Store::new(module.engine(), limits);
+        store.limiter(|limits| limits);
+        let instance = Instance::new(&mut store, module, &[])?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or("the wasm selector must export its `memory`")?;
+        let alloc = instance.get_typed_func::<u32, u32>(&mut store, "alloc")?;
+        let select = instance.get_typed_func::<SelectParams, i32>(&mut store, "select")?;
+
+        Ok(Sandbox {
+            store,
+            memory,
+            alloc,
+            select,
+            buffer: None,
+        })
+    }
+
+    /// Addresses of the candidates and selection buffers, big enough for `count` candidates.
+    fn buffers(&mut self, count: usize) -> Result<(u32, u32), Box<dyn Error>> {
+        let (address, capacity) = match self.buffer {
+            Some((address, capacity)) if capacity >= count => (address, capacity),
+            _ => {
+                let capacity = cmp::max(count * 2, 64);
+                let address = self.alloc.call(&mut self.store, (capacity * 12) as u32)?;
+                self.buffer = Some((address, capacity));
+                (address, capacity)
+            },
+        };
+
+        Ok((address, address + (capacity * 8) as u32))
+    }
+}
+
+impl WasmCoinSelect {
+    pub fn new(module_path: &str, fuel: u64, max_memory: usize, long_term_feerate: f32, dust_limit: u64, input_drain_weight: u32, output_drain_weight: u32) -> Result<Self, Box<dyn Error>> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        // Traps are logged in a line, without the backtrace of the module.
+        config.wasm_backtrace(false);
+        let engine = Engine::new(&config)?;
+        let module = Module::from_file(&engine, module_path)?;
+        let sandbox = Sandbox::new(&module, max_memory)?;
+
+        Ok(WasmCoinSelect {
+            module,
+            sandbox,
+            fuel,
+            max_memory,
+            pool: CandidatePool::new(long_term_feerate, dust_limit, input_drain_weight, output_drain_weight),
+        })
+    }
+}
+
+impl TargetSelector for WasmCoinSelect {
+    fn values(&self) -> Result<Vec<u64>, Box<dyn Error>> {
+        Ok(self.pool.values().to_vec())
+    }
+
+    fn deposit(&mut self, deposit: ScenarioEntry) -> Result<(), Box<dyn Error>> {
+        self.pool.push(Amount::from_btc(deposit.amount)?.to_sat());
+        Ok(())
+    }
+
+    fn load(&mut self, values: &[u64]) -> Result<(), Box<dyn Error>> {
+        self.pool.load(values);
+        Ok(())
+    }
+
+    fn withdraw(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Result<SimulationEntry, Box<dyn Error>> {
+        let values = self.pool.values().to_vec();
+        let target = payments.iter().map(|x| x.amount).sum::<u64>();
+        let long_term_feerate = self.pool.long_term_feerate as f64;
+
+        // The indices selected by the module, `None` when it found no selection.
+        let mut select = || -> Result<Option<Vec<usize>>, Box<dyn Error>> {
+            let sandbox = &mut self.sandbox;
+            sandbox.store.set_fuel(self.fuel)?;
+            let (candidates, out) = sandbox.buffers(values.len())?;
+            let bytes = values.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>();
+            sandbox.memory.write(&mut sandbox.store, candidates as usize, &bytes)?;
+
+            let params = (
+                candidates,
+                values.len() as u32,
+                target,
+                pool::sat_per_vb(fee_rate_per_kvb),
+                long_term_feerate,
+                pool::base_weight(payments),
+                SEGWIT_V1_TXIN_WEIGHT,
+                out,
+            );
+            let count = match sandbox.select.call(&mut sandbox.store, params)? {
+                count if count < 0 => return Ok(None),
+                count if count as usize > values.len() => {
+                    return Err(format!("selected {} out of {} candidates", count, values.len()).into());
+                },
+                count => count as usize,
+            };
+
+            let mut indices = vec![0_u8; count * 4];
+            sandbox.memory.read(&sandbox.store, out as usize, &mut indices)?;
+            Ok(Some(indices
+                .chunks_exact(4)
+                .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]) as usize)
+                .collect()))
+        };
+
+        // The pool is left untouched by a faulty module, so the simulation goes on.
+        let selection = match select() {
+            Ok(Some(selection)) => selection,
+            Ok(None) => return Ok(self.pool.entry(payments, fee_rate_per_kvb)),
+            Err(err) => {
+                match self.sandbox.store.get_fuel() {
+                    Ok(0) => event!(Level::INFO, "wasm selector ran out of fuel"),
+                    _ => event!(Level::ERROR, "wasm selector failed: {}", err),
+                }
+                self.sandbox = Sandbox::new(&self.module, self.max_memory)?;
+                return Ok(self.pool.entry(payments, fee_rate_per_kvb));
+            },
+        };
+
+        match self.pool.apply(&selection, payments, fee_rate_per_kvb, "wasm") {
+            Ok(entry) => Ok(entry),
+            Err(err) => {
+                event!(Level::ERROR, "wasm selector made an invalid selection: {}", err);
+                Ok(self.pool.entry(payments, fee_rate_per_kvb))
+            },
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    error::Error,
+    io::{ BufRead, BufReader, Write },
+    process::{ Child, ChildStdin, Command, Stdio },
+    sync::mpsc::{ self, Receiver, RecvTimeoutError },
+    thread,
+    time::Duration,
+};
+
+use serde_json::{ json, Value };
+
+/// A child process answering requests as newline delimited JSON over its standard input and
+/// output.
+///
+/// Each message is answered by a single line. Through [`Worker::call`], requests are
+/// `{"method": ..., "params": ...}` lines answered by either `{"result": ...}` or
+/// `{"error": "..."}`. Anything the child writes to its standard error is passed through to ours.
+pub struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    responses: Receiver<std::io::Result<String>>,
+    timeout: Duration,
+}
+
+/// The worker processed the request but reported it couldn't fulfill it.
+#[derive(Debug)]
+pub struct RemoteError(pub String);
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "worker error: {}", self.0)
+    }
+}
+
+impl Error for RemoteError {}
+
+impl Worker {
+    pub fn spawn(command: &mut Command, timeout: Duration) -> Result<Self, Box<dyn Error>> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+
+        let stdin = child.stdin.take().expect("stdin should be piped.");
+        let stdout = child.stdout.take().expect("stdout should be piped.");
+
+        // Responses are read on their own thread so a hanging worker can be abandoned after
+        // `timeout` instead of blocking the simulation forever.
+        let (sender, responses) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Worker {
+            child,
+            stdin,
+            responses,
+            timeout,
+        })
+    }
+
+    /// Sends `message` as a single line and waits for the single line answering it.
+    pub fn request(&mut self, message: &Value) -> Result<Value, Box<dyn Error>> {
+        writeln!(self.stdin, "{}", message)?;
+        self.stdin.flush()?;
+
+        let line = match self.responses.recv_timeout(self.timeout) {
+            Ok(line) => line?,
+            Err(RecvTimeoutError::Timeout) => {
+                // The worker state is unknown from now on, so it can't be trusted with further
+                // requests.
+                self.kill();
+                return Err(format!("worker didn't answer within {:?}", self.timeout).into());
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(From::from("worker exited before answering"));
+            }
+        };
+
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    pub fn call(&mut self, method: &str, params: Value) -> Result<Value, Box<dyn Error>> {
+        let mut response = self.request(&json!({ "method": method, "params": params }))
+            .map_err(|err| format!("`{}` request failed: {}", method, err))?;
+
+        if let Some(error) = response.get("error") {
+            return Err(Box::new(RemoteError(error.as_str().unwrap_or_default().to_string())));
+        }
+
+        Ok(response["result"].take())
+    }
+
+    fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
@@ -1,7 +1,10 @@
-use crate::PaymentPolicy;
-use crate::SEGWIT_V1_TXOUT_WEIGHT;
-use crate::models::{ PendingPayment, ScenarioEntry, SimulationSummary };
-use crate::selectors::TargetSelector;
+use crate::{ SEGWIT_V1_TXIN_WEIGHT, SEGWIT_V1_TXOUT_WEIGHT };
+use crate::models::{ FailureReason, PendingPayment, ScenarioEntry, SimulationEntry, SimulationSummary, TransactionType, Urgency };
+use crate::policy::{ BumpStrategy, ConsolidationPolicy, DeferralPolicy, PartialFulfillment, PaymentPolicy };
+use crate::selectors::{ pool, TargetSelector };
+use crate::selectors::pool::{ CandidatePool, WasteParams };
+use crate::selectors::optimal::Objective;
+use crate::wallet::{ Spendable, Transaction, Wallet };
 
 use std::{
     error::Error,
@@ -19,15 +22,16 @@ struct SimulationRecorder<T: std::io::Write> {
     inputs_writer: Writer<T>,
     samples_writer: Writer<T>,
     results_writer: Writer<T>,
+    payments_writer: Writer<T>,
+    shadow_writer: Option<Writer<T>>,
 }
 
 impl<T: std::io::Write> SimulationRecorder<T> {
-    fn new(output_path: String) -> Result<SimulationRecorder<std::fs::File>, Box<dyn Error>> {
+    fn new(output_path: String, shadow: bool) -> Result<SimulationRecorder<std::fs::File>, Box<dyn Error>> {
         fs::create_dir_all(&output_path)?;
 
         let full_results_file = OpenOptions::new()
             .create_new(true)
-            .write(true)
             .append(true)
             .open(format!("{}/full_results.csv", &output_path))?;
         let results_writer = csv::WriterBuilder::new()
@@ -36,7 +40,6 @@ impl<T: std::io::Write> SimulationRecorder<T> {
 
         let inputs_file = OpenOptions::new()
             .create_new(true)
-            .write(true)
             .append(true)
             .open(format!("{}/inputs.csv", &output_path))?;
         let inputs_writer = csv::WriterBuilder::new()
@@ -45,7 +48,6 @@ impl<T: std::io::Write> SimulationRecorder<T> {
 
         let results_sample_file = OpenOptions::new()
             .create_new(true)
-            .write(true)
             .append(true)
             .open(format!("{}/results.csv", &output_path))?;
         let samples_writer = csv::WriterBuilder::new()
@@ -54,18 +56,37 @@ impl<T: std::io::Write> SimulationRecorder<T> {
 
         let utxos_file = OpenOptions::new()
             .create_new(true)
-            .write(true)
             .append(true)
             .open(format!("{}/utxos.csv", &output_path))?;
         let utxos_writer = csv::WriterBuilder::new()
             .has_headers(false)
             .from_writer(utxos_file);
 
+        let payments_file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(format!("{}/payments.csv", &output_path))?;
+        let payments_writer = csv::WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(payments_file);
+
+        let shadow_writer = if shadow {
+            let shadow_file = OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(format!("{}/shadow_results.csv", &output_path))?;
+            Some(csv::WriterBuilder::new()
+                .has_headers(true)
+                .from_writer(shadow_file))
+        } else { None };
+
         Ok(SimulationRecorder {
             utxos_writer,
             inputs_writer,
             samples_writer,
             results_writer,
+            payments_writer,
+            shadow_writer,
         })
     }
 
@@ -74,83 +95,585 @@ impl<T: std::io::Write> SimulationRecorder<T> {
         self.inputs_writer.flush()?;
         self.samples_writer.flush()?;
         self.results_writer.flush()?;
+        self.payments_writer.flush()?;
+        if let Some(shadow_writer) = self.shadow_writer.as_mut() {
+            shadow_writer.flush()?;
+        }
         
         Ok(())
     }
 }
 
+/// Selectors asked at every withdrawal what they would select from the UTXOs of the simulated
+/// selector, without applying their selection. This compares the algorithms on the same inputs
+/// instead of on the UTXO pools each one would have evolved.
+pub struct ShadowSelectors {
+    /// Name given to the simulated selector in `shadow_results.csv`.
+    pub primary: String,
+    pub selectors: Vec<(String, Box<dyn TargetSelector>)>,
+    /// Feerate and weights used to compute the waste of all the selections alike.
+    pub waste_params: WasteParams,
+    /// Cost minimized by the `optimal` selector, used to measure how far the others are from it.
+    pub objective: Objective,
+}
+
+impl ShadowSelectors {
+    /// The cost of the selection of the `optimal` selector, if it's among the simulated ones and
+    /// searched the whole UTXO pool.
+    fn optimum<'a>(&self, mut entries: impl Iterator<Item = (&'a str, &'a SimulationEntry)>, fee_rate_per_kvb: f32) -> Option<f64> {
+        entries
+            .find(|(name, entry)| *name == "optimal" && entry.algorithm == "optimal")
+            .and_then(|(_, entry)| self.objective.score(entry, fee_rate_per_kvb, &self.waste_params))
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ShadowEntry<'a> {
+    id: usize,
+    selector: &'a str,
+    algorithm: &'a str,
+    fee: Option<i64>,
+    waste: Option<f64>,
+    input_count: usize,
+    change_amount: Option<u64>,
+    optimality_gap: Option<f64>,
+}
+
+impl<'a> ShadowEntry<'a> {
+    fn new(selector: &'a str, entry: &'a SimulationEntry, fee_rate_per_kvb: f32, shadow: &ShadowSelectors, optimum: Option<f64>) -> Self {
+        ShadowEntry {
+            id: entry.id,
+            selector,
+            algorithm: &entry.algorithm,
+            fee: entry.fee,
+            waste: pool::waste(entry, fee_rate_per_kvb, &shadow.waste_params),
+            input_count: entry.inputs.len(),
+            change_amount: entry.change_amount,
+            optimality_gap: optimum.and_then(|optimum| {
+                shadow.objective
+                    .score(entry, fee_rate_per_kvb, &shadow.waste_params)
+                    .map(|score| score - optimum)
+            }),
+        }
+    }
+}
+
+/// What became of a payment, saved to `payments.csv`.
+#[derive(serde::Serialize)]
+struct PaymentRecord {
+    arrival: usize,
+    amount: u64,
+    urgency: Option<Urgency>,
+    attempts: usize,
+    /// `paid`, `expired`, `dropped` or `queued` when the scenario ended.
+    outcome: &'static str,
+    /// The withdrawal which paid the payment.
+    withdraw_attempt: Option<usize>,
+    /// Scenario rows from the arrival of the payment until it was paid.
+    wait: Option<usize>,
+}
+
+impl PaymentRecord {
+    fn new(payment: &PendingPayment, outcome: &'static str) -> Self {
+        PaymentRecord {
+            arrival: payment.arrival,
+            amount: payment.amount,
+            urgency: payment.urgency,
+            attempts: payment.attempts,
+            outcome,
+            withdraw_attempt: None,
+            wait: None,
+        }
+    }
+}
+
+/// A consolidation policy and the accounting of the consolidation transactions it asks for,
+/// which are applied to the simulated selector UTXOs.
+pub struct Consolidator {
+    pub policy: Box<dyn ConsolidationPolicy>,
+    pub pool: CandidatePool,
+}
+
+/// How the wallet bumps the transactions which missed a block, when the blocks only include the
+/// ones paying enough.
+pub struct FeeBumper {
+    pub strategy: BumpStrategy,
+    /// Smallest change a CPFP child may keep.
+    pub dust_limit: u64,
+}
+
+/// Puts `payments` back in the `queue`, keeping it in arrival order.
+fn requeue(queue: &mut Vec<PendingPayment>, payments: Vec<PendingPayment>) {
+    queue.extend(payments);
+    queue.sort_by_key(|payment| payment.arrival);
+}
+
+/// Size in vbytes of the transaction of `entry` paying `payments`.
+fn transaction_size(entry: &SimulationEntry, payments: &[PendingPayment]) -> u64 {
+    let change_weight = entry.change_amount.map_or(0, |_| SEGWIT_V1_TXOUT_WEIGHT);
+    (pool::base_weight(payments) + entry.inputs.len() as u32 * SEGWIT_V1_TXIN_WEIGHT + change_weight) as u64
+}
+
+/// The record of the selection of `entry` as a failed one, when it can't be broadcast.
+fn rejected(entry: SimulationEntry) -> SimulationEntry {
+    SimulationEntry {
+        id: entry.id,
+        transaction_type: entry.transaction_type,
+        amount: entry.amount,
+        target_feerate: entry.target_feerate,
+        algorithm: String::from("failed"),
+        eligibility_filter: entry.eligibility_filter,
+        utxo_count_before_payment: entry.utxo_count_before_payment,
+        utxo_count_after_payment: entry.utxo_count_before_payment,
+        cost_to_empty_at_long_term_feerate: entry.cost_to_empty_at_long_term_feerate,
+        balance: entry.balance,
+        ..Default::default()
+    }
+}
+
+fn utxo_amounts(values: &[u64]) -> String {
+    values
+        .iter().map(|x| Amount::from_sat(*x).to_string_in(Denomination::Satoshi))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
 pub struct Simulation<'a> {
     pub payment_policy: PaymentPolicy,
-    pub selector: &'a mut (dyn TargetSelector + 'a)
+    /// Failed withdrawals after which a queued payment is given up.
+    pub payment_expiry: Option<usize>,
+    /// Which payments are sent when the whole queue can't be funded, all of them if not given.
+    pub partial_fulfillment: Option<PartialFulfillment>,
+    /// Rounds of the max value partial fulfillment search.
+    pub partial_fulfillment_rounds: usize,
+    pub deferral: Option<DeferralPolicy>,
+    pub selector: &'a mut (dyn TargetSelector + 'a),
+    pub shadow: Option<ShadowSelectors>,
+    pub consolidation: Option<Consolidator>,
+    /// The UTxOs and their confirmations when only confirmed ones are given to the selector,
+    /// otherwise the selector keeps the UTxOs itself.
+    pub wallet: Option<Wallet>,
+    pub fee_bumping: Option<FeeBumper>,
 }
 
 impl Simulation<'_> {
-    pub fn run(&mut self, input_path: &str, output_path: &str) -> Result<(), Box<dyn Error>> {
+    /// The UTxOs of the eligibility `tier` when confirmations are simulated, otherwise the UTxOs
+    /// of the selector.
+    fn spendable(&self, tier: usize) -> Result<Spendable, Box<dyn Error>> {
+        match self.wallet.as_ref() {
+            Some(wallet) => Ok(wallet.eligible(&wallet.tiers[tier])),
+            None => Ok(Spendable {
+                values: self.selector.values()?,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Whether the wallet can broadcast the transaction of `entry`, paying `payments`, without
+    /// breaking the chain limits.
+    fn within_limits(&self, spendable: &Spendable, entry: &SimulationEntry, payments: &[PendingPayment]) -> Result<bool, Box<dyn Error>> {
+        match self.wallet.as_ref() {
+            Some(wallet) => Ok(wallet.within_limits(&wallet.parents(&spendable.candidates, &entry.inputs)?, transaction_size(entry, payments))),
+            None => Ok(true),
+        }
+    }
+
+    /// Whether the selector would have funded `payments` had the UTxOs left out of `spendable`
+    /// by the chain limits been given to it as well.
+    ///
+    /// The selector is run again on all of them, leaving the statistics of this run instead of the
+    /// ones of the failed selection. Running a randomized selector again would change its later
+    /// selections, so for them it's estimated from the effective value of the UTxOs instead.
+    fn funded_without_limits(&mut self, spendable: &Spendable, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Result<bool, Box<dyn Error>> {
+        if spendable.chain_limited.is_empty() {
+            return Ok(false);
+        }
+
+        let values = [spendable.values.as_slice(), spendable.chain_limited.as_slice()].concat();
+        if self.selector.randomized() {
+            let cost = payments.iter().map(|payment| pool::payment_cost(payment, fee_rate_per_kvb)).sum::<u64>();
+            return Ok(pool::funds(&values, fee_rate_per_kvb) >= cost);
+        }
+
+        self.selector.load(&values)?;
+        let funded = self.selector.withdraw(payments, fee_rate_per_kvb)?.algorithm != "failed";
+        // The selector is given back the UTxOs it may actually spend.
+        self.selector.load(&spendable.values)?;
+
+        Ok(funded)
+    }
+
+    /// Withdraws `payments` from the UTxOs of each eligibility tier in turn until one of them
+    /// funds the payments, returning the selection with the UTxOs it was made from.
+    fn select(&mut self, payments: &[PendingPayment], fee_rate_per_kvb: f32) -> Result<(Spendable, SimulationEntry), Box<dyn Error>> {
+        let tiers = self.wallet.as_ref().map_or(1, |wallet| wallet.tiers.len());
+        let mut tier = 0;
+        loop {
+            let spendable = self.spendable(tier)?;
+            if self.wallet.is_some() {
+                self.selector.load(&spendable.values)?;
+            }
+            let mut entry = self.selector.withdraw(payments, fee_rate_per_kvb)?;
+            entry.eligibility_filter = self.wallet.as_ref().map(|wallet| wallet.tiers[tier].to_string());
+
+            // The UTxOs are only left out of the selection one by one, so together they may
+            // still make a chain too long.
+            if entry.algorithm != "failed" && !self.within_limits(&spendable, &entry, payments)? {
+                entry = rejected(entry);
+                entry.failure_reason = Some(FailureReason::ChainLimits);
+            } else if entry.algorithm == "failed" {
+                // Only the failure of the last tier is recorded, so the earlier ones aren't told
+                // apart.
+                entry.failure_reason = match tier + 1 == tiers && self.funded_without_limits(&spendable, payments, fee_rate_per_kvb)? {
+                    true => Some(FailureReason::ChainLimits),
+                    false => Some(FailureReason::Selection),
+                };
+            }
+
+            entry.payment_amounts = payments.iter().map(|payment| payment.amount).collect();
+            entry.fee_rate_per_kvb = fee_rate_per_kvb;
+
+            tier += 1;
+            if entry.algorithm != "failed" || tier == tiers {
+                return Ok((spendable, entry));
+            }
+        }
+    }
+
+    /// Applies to the wallet the transaction of `entry`, paying `payments` from some of the
+    /// `spendable` UTxOs given to the selector, and records in the entry the whole wallet instead
+    /// of its spendable part.
+    fn settle(&mut self, spendable: &Spendable, entry: &mut SimulationEntry, payments: &[PendingPayment]) -> Result<(), Box<dyn Error>> {
+        let Some(wallet) = self.wallet.as_mut() else { return Ok(()) };
+
+        entry.utxo_count_before_payment = wallet.utxo_count();
+        entry.balance = wallet.balance();
+        entry.cost_to_empty_at_long_term_feerate = wallet.cost_to_empty_at_long_term_feerate();
+        if entry.algorithm != "failed" {
+            let transaction = Transaction {
+                size: transaction_size(entry, payments),
+                fee: entry.fee.unwrap_or_default().max(0) as u64,
+                payments: payments.to_vec(),
+            };
+            wallet.spend(&spendable.candidates, &entry.inputs, entry.change_amount, transaction)?;
+        }
+        entry.utxo_count_after_payment = wallet.utxo_count();
+
+        Ok(())
+    }
+
+    /// Bumps the stuck wallet transaction `tx` to `fee_rate_per_kvb`, returning the record of the
+    /// bump with the UTxOs it was made from, or `None` if it can't be bumped.
+    fn bump(&mut self, tx: usize, fee_rate_per_kvb: f32) -> Result<Option<(Spendable, SimulationEntry)>, Box<dyn Error>> {
+        let (Some(bumper), Some(wallet)) = (self.fee_bumping.as_ref(), self.wallet.as_mut()) else { return Ok(None) };
+        if !wallet.is_stuck(tx) {
+            return Ok(None);
+        }
+        let before = wallet.clone();
+
+        let (spendable, mut entry, payments, bumped_fee) = match bumper.strategy {
+            BumpStrategy::Rbf => {
+                let (original, restored) = wallet.unbroadcast(tx)?;
+                let mut spendable = wallet.eligible(&wallet.tiers[wallet.tiers.len() - 1]);
+                for index in restored.iter() {
+                    if !spendable.candidates.contains(index) {
+                        spendable.candidates.push(*index);
+                        spendable.values.push(wallet.value(*index));
+                    }
+                }
+
+                self.selector.load(&spendable.values)?;
+                let entry = self.selector.withdraw(&original.payments, fee_rate_per_kvb)?;
+                // A replacement must spend some input of the original, or both could confirm,
+                // and pay enough more fees than it.
+                let size = transaction_size(&entry, &original.payments);
+                let replaces = entry.algorithm != "failed"
+                    && wallet.spent(&spendable.candidates, &entry.inputs)?.iter().any(|x| restored.contains(x))
+                    && original.replaceable_by(entry.fee.unwrap_or_default().max(0) as u64, size)
+                    && wallet.within_limits(&wallet.parents(&spendable.candidates, &entry.inputs)?, size);
+                if !replaces {
+                    *wallet = before;
+                    return Ok(None);
+                }
+
+                (spendable, entry, original.payments, original.fee)
+            },
+            BumpStrategy::Cpfp => {
+                let Some(index) = wallet.change_of(tx) else { return Ok(None) };
+                let change = wallet.value(index);
+                // The child pays for the package and keeps the rest as change.
+                let size = (pool::base_weight(&[]) + SEGWIT_V1_TXIN_WEIGHT + SEGWIT_V1_TXOUT_WEIGHT) as u64;
+                let fee = wallet.child_fee(tx, size, fee_rate_per_kvb);
+                let Some(output) = change.checked_sub(fee).filter(|x| *x >= bumper.dust_limit) else { return Ok(None) };
+                if !wallet.within_limits(&[tx], size) {
+                    return Ok(None);
+                }
+
+                let entry = SimulationEntry {
+                    inputs: vec![change],
+                    fee: Some(fee as i64),
+                    target_feerate: pool::sat_per_vb(fee_rate_per_kvb) as f32,
+                    real_feerate: Some(fee as f32 / size as f32),
+                    algorithm: String::from("cpfp"),
+                    output_count: Some(1),
+                    change_amount: Some(output),
+                    ..Default::default()
+                };
+                let spendable = Spendable {
+                    candidates: vec![index],
+                    values: vec![change],
+                    ..Default::default()
+                };
+                (spendable, entry, Vec::new(), 0)
+            },
+        };
+
+        entry.transaction_type = TransactionType::FeeBump;
+        entry.payment_amounts = payments.iter().map(|payment| payment.amount).collect();
+        entry.fee_rate_per_kvb = fee_rate_per_kvb;
+        self.settle(&spendable, &mut entry, &payments)?;
+        // A replacement got back the inputs of the original before spending them again.
+        entry.utxo_count_before_payment = before.utxo_count();
+        entry.balance = before.balance();
+        entry.cost_to_empty_at_long_term_feerate = before.cost_to_empty_at_long_term_feerate();
+        entry.fee = entry.fee.map(|fee| fee - bumped_fee as i64);
+
+        Ok(Some((spendable, entry)))
+    }
+
+    /// Simulates the scenario at `input_path`, saving the results in `output_path`, and returns
+    /// the final summary.
+    pub fn run(&mut self, input_path: &str, output_path: &str) -> Result<SimulationSummary, Box<dyn Error>> {
         let mut simulation_summary = SimulationSummary::default();
-        simulation_summary.scenario_file = input_path.split('/').last().expect("There should be at least one element in path.").to_string();
+        simulation_summary.scenario_file = input_path.split('/').next_back().expect("There should be at least one element in path.").to_string();
 
         let scenario_file = File::open(input_path)?;
+        // Only some rows need the urgency and block columns, so rows may have different lengths.
         let mut reader = csv::ReaderBuilder::new()
             .has_headers(false)
+            .flexible(true)
             .from_reader(scenario_file);
 
+        // Queued payments, in their arrival order.
         let mut payments: Vec<PendingPayment> = Vec::new();
         let mut withdraw_attempt: usize = 0;
-        let mut simulation_recorder = <SimulationRecorder<std::fs::File>>::new(output_path.to_string())?;
+        let mut simulation_recorder = <SimulationRecorder<std::fs::File>>::new(output_path.to_string(), self.shadow.is_some())?;
+
+        // The whole scenario is read beforehand, as clairvoyant policies look at the rows ahead.
+        let scenario = reader.deserialize().collect::<Result<Vec<ScenarioEntry>, _>>()?;
+
+        for (position, record) in scenario.iter().enumerate() {
+            simulation_summary.queue_lengths.push(payments.len() as f32);
+            let found = match self.wallet.as_mut() {
+                Some(wallet) => wallet.advance(position, record)?,
+                None => false,
+            };
 
-        for result in reader.deserialize() {
-            let record: ScenarioEntry = result?;
+            if let (true, Some(wallet)) = (found && self.fee_bumping.is_some(), self.wallet.as_ref()) {
+                // The transactions which missed the block are bumped to the feerate it asked
+                // for, or the current one if higher.
+                let fee_rate_per_kvb = record.fee_rate_per_kvb.max(wallet.last_inclusion_feerate().unwrap_or_default());
+                for tx in wallet.stuck() {
+                    let Some((spendable, mut bump_entry)) = self.bump(tx, fee_rate_per_kvb)? else { continue };
+                    withdraw_attempt += 1;
 
-            if record.amount > 0.0 {
+                    event!(Level::INFO, "bumped transaction {} with {}", tx, bump_entry.algorithm);
+
+                    bump_entry.id = withdraw_attempt;
+                    simulation_summary.update(&bump_entry)?;
+                    simulation_recorder.utxos_writer.serialize((withdraw_attempt, utxo_amounts(&spendable.values)))?;
+                    simulation_recorder.inputs_writer.serialize((withdraw_attempt, utxo_amounts(&bump_entry.inputs)))?;
+                    simulation_recorder.results_writer.serialize(bump_entry)?;
+                }
+            }
+
+            if self.consolidation.is_some() {
+                // Consolidations aren't urgent, so they only spend the UTxOs of the strictest tier.
+                let spendable = self.spendable(0)?;
+                let utxos = &spendable.values;
+                let consolidation = self.consolidation.as_mut().expect("checked above");
+                consolidation.pool.load(utxos);
+                let pool = &consolidation.pool;
+                let wallet = self.wallet.as_ref();
+                // The merged output must be worth creating, or the UTXOs would be given away as
+                // fees, and the transaction must stay within the chain limits.
+                let selection = consolidation.policy
+                    .consolidate(&scenario, position, utxos)
+                    .filter(|selection| {
+                        let inputs = selection.iter().map(|index| utxos[*index]).collect::<Vec<u64>>();
+                        let size = (pool::base_weight(&[]) + inputs.len() as u32 * SEGWIT_V1_TXIN_WEIGHT + pool.output_drain_weight) as u64;
+                        pool
+                            .transaction(selection.len(), inputs.iter().sum(), &[], record.fee_rate_per_kvb)
+                            .is_some_and(|(_, output)| output.is_some())
+                            && wallet.is_none_or(|wallet| {
+                                wallet
+                                    .parents(&spendable.candidates, &inputs)
+                                    .is_ok_and(|parents| wallet.within_limits(&parents, size))
+                            })
+                    });
+
+                if let Some(selection) = selection {
+                    withdraw_attempt += 1;
+                    let mut consolidation_entry = consolidation.pool.apply(&selection, &[], record.fee_rate_per_kvb, consolidation.policy.name())?;
+                    self.selector.load(consolidation.pool.values())?;
+
+                    event!(Level::INFO, "consolidated {} utxos", consolidation_entry.inputs.len());
+
+                    self.settle(&spendable, &mut consolidation_entry, &[])?;
+                    consolidation_entry.eligibility_filter = self.wallet.as_ref().map(|wallet| wallet.tiers[0].to_string());
+                    consolidation_entry.id = withdraw_attempt;
+                    consolidation_entry.transaction_type = TransactionType::Consolidation;
+                    consolidation_entry.fee_rate_per_kvb = record.fee_rate_per_kvb;
+                    simulation_summary.update(&consolidation_entry)?;
+                    simulation_recorder.utxos_writer.serialize((withdraw_attempt, utxo_amounts(&spendable.values)))?;
+                    simulation_recorder.inputs_writer.serialize((withdraw_attempt, utxo_amounts(&consolidation_entry.inputs)))?;
+                    simulation_recorder.results_writer.serialize(consolidation_entry)?;
+                }
+            }
+
+            let arrived = record.amount <= 0.0;
+            if arrived {
+                payments.push(PendingPayment {
+                    amount: Amount::from_btc(-record.amount)?.to_sat(),
+                    weight: SEGWIT_V1_TXOUT_WEIGHT,
+                    arrival: position,
+                    attempts: 0,
+                    urgency: record.urgency,
+                });
+            } else {
                 simulation_summary.deposit_count += 1;
-                self.selector.deposit(record)?;
+                match self.wallet.as_mut() {
+                    Some(wallet) => wallet.receive(Amount::from_btc(record.amount)?.to_sat()),
+                    None => self.selector.deposit(record.clone())?,
+                }
+            }
+
+            // Deferred payments stay queued while the feerate is high, and once released they're
+            // sent as if they had just arrived.
+            let cheap = self.deferral.as_mut().is_none_or(|deferral| deferral.observe(record.fee_rate_per_kvb));
+            let deferral = self.deferral.as_ref();
+            let (mut ready, deferred) = std::mem::take(&mut payments)
+                .into_iter()
+                .partition::<Vec<_>, _>(|payment| !deferral.is_some_and(|x| x.defers(payment, position, cheap)));
+            payments = deferred;
+            let released = deferral.is_some() && ready.iter().any(|payment| payment.arrival < position && payment.attempts == 0);
+
+            // Batches may become due on any row, as time passes or the feerate drops.
+            let waited = ready.first().map_or(0, |payment| position - payment.arrival);
+            if ready.is_empty() || !self.payment_policy.is_due(&ready, arrived || released, waited, record.fee_rate_per_kvb) {
+                requeue(&mut payments, ready);
                 continue;
             }
 
             withdraw_attempt += 1;
 
-            payments.push(PendingPayment {
-                amount: Amount::from_btc(-1.0 * record.amount)?.to_sat(),
-                weight: SEGWIT_V1_TXOUT_WEIGHT
-            });
+            // The payments sent by this withdrawal, the rest stay queued. They are fitted in the
+            // UTxOs of the most permissive eligibility tier.
+            let mut batch = match self.partial_fulfillment {
+                Some(strategy) => {
+                    let tiers = self.wallet.as_ref().map_or(1, |wallet| wallet.tiers.len());
+                    let funds = self.spendable(tiers - 1)?;
+                    strategy.split(&mut ready, &funds.values, record.fee_rate_per_kvb, self.partial_fulfillment_rounds)
+                },
+                None => std::mem::take(&mut ready),
+            };
+            requeue(&mut payments, ready);
 
-            let utxo_amounts = self.selector
-                .values()
-                .into_iter().map(|x| Amount::from_sat(x).to_string_in(Denomination::Satoshi))
-                .collect::<Vec<String>>()
-                .join(",");
-            simulation_recorder.utxos_writer.serialize((withdraw_attempt, utxo_amounts))?;
+            let (spendable, mut simulation_entry) = self.select(&batch, record.fee_rate_per_kvb)?;
+            simulation_recorder.utxos_writer.serialize((withdraw_attempt, utxo_amounts(&spendable.values)))?;
+            self.settle(&spendable, &mut simulation_entry, &batch)?;
 
-            let mut simulation_entry = self.selector.withdraw(&payments, record.fee_rate_per_kvb);
+            let mut shadow_entries = Vec::new();
+            if let Some(shadow) = self.shadow.as_mut() {
+                for (_, selector) in shadow.selectors.iter_mut() {
+                    selector.load(&spendable.values)?;
+                    let mut shadow_entry = selector.withdraw(&batch, record.fee_rate_per_kvb)?;
+                    shadow_entry.id = withdraw_attempt;
+                    shadow_entries.push(shadow_entry);
+                }
+            }
 
             event!(Level::INFO, "withdraw {}/? finished", withdraw_attempt);
 
-            match self.payment_policy {
-                PaymentPolicy::Drop => payments.clear(),
-                PaymentPolicy::RollForward if simulation_entry.algorithm != "failed" => payments.clear(),
-                _ => (),
+            for payment in batch.iter_mut() {
+                payment.attempts += 1;
+            }
+
+            if simulation_entry.algorithm != "failed" {
+                for payment in batch.drain(..) {
+                    simulation_summary.payment_waits.push((position - payment.arrival) as f32);
+                    simulation_recorder.payments_writer.serialize(PaymentRecord {
+                        withdraw_attempt: Some(withdraw_attempt),
+                        wait: Some(position - payment.arrival),
+                        ..PaymentRecord::new(&payment, "paid")
+                    })?;
+                }
+            } else {
+                let retries = self.payment_policy.retries();
+                let expiry = self.payment_expiry;
+                let (expired, queued) = batch
+                    .drain(..)
+                    .partition::<Vec<_>, _>(|payment| !retries || expiry.is_some_and(|x| payment.attempts >= x));
+                requeue(&mut payments, queued);
+
+                for payment in expired {
+                    // Giving up failed payments is what the `drop` policy does, not a problem
+                    // worth a warning for each of them.
+                    if retries {
+                        event!(Level::WARN, "payment of {} sats arrived at row {} expired after {} attempts", payment.amount, payment.arrival, payment.attempts);
+                        simulation_summary.expired_payment_count += 1;
+                        simulation_recorder.payments_writer.serialize(PaymentRecord::new(&payment, "expired"))?;
+                    } else {
+                        event!(Level::DEBUG, "payment of {} sats arrived at row {} dropped", payment.amount, payment.arrival);
+                        simulation_summary.dropped_payment_count += 1;
+                        simulation_recorder.payments_writer.serialize(PaymentRecord::new(&payment, "dropped"))?;
+                    }
+                }
             }
 
             simulation_entry.id = withdraw_attempt;
 
+            if let (Some(shadow), Some(shadow_writer)) = (self.shadow.as_ref(), simulation_recorder.shadow_writer.as_mut()) {
+                let entries = || {
+                    std::iter::once((shadow.primary.as_str(), &simulation_entry))
+                        .chain(shadow.selectors.iter().map(|(name, _)| name.as_str()).zip(shadow_entries.iter()))
+                };
+                let optimum = shadow.optimum(entries(), record.fee_rate_per_kvb);
+                for (name, entry) in entries() {
+                    shadow_writer.serialize(ShadowEntry::new(name, entry, record.fee_rate_per_kvb, shadow, optimum))?;
+                }
+            }
+
             simulation_summary.update(&simulation_entry)?;
 
-            let input_amounts = simulation_entry.inputs
-                .iter()
-                .map(|x| Amount::from_sat(*x).to_string_in(Denomination::Satoshi))
-                .collect::<Vec<String>>()
-                .join(",");
-            simulation_recorder.inputs_writer.serialize((withdraw_attempt, input_amounts))?;
+            simulation_recorder.inputs_writer.serialize((withdraw_attempt, utxo_amounts(&simulation_entry.inputs)))?;
 
-            if withdraw_attempt != 0 && withdraw_attempt % 500 == 0 {
+            if withdraw_attempt != 0 && withdraw_attempt.is_multiple_of(500) {
                 simulation_recorder.samples_writer.serialize(&simulation_summary)?;
             };
 
             simulation_recorder.results_writer.serialize(simulation_entry)?;
         }
         
+        if !payments.is_empty() {
+            event!(Level::WARN, "{} payments were still queued at the end of the scenario", payments.len());
+        }
+        for payment in payments.iter() {
+            simulation_recorder.payments_writer.serialize(PaymentRecord::new(payment, "queued"))?;
+        }
+
         simulation_recorder.flush()?;
 
-        Ok(())
+        let summary_file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(format!("{}/summary.csv", output_path))?;
+        let mut summary_writer = csv::WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(summary_file);
+        summary_writer.serialize(&simulation_summary)?;
+        summary_writer.flush()?;
+
+        Ok(simulation_summary)
     }
 }
@@ -0,0 +1,191 @@
+use crate::models::SimulationSummary;
+
+use std::{
+    error::Error,
+    fs::OpenOptions,
+};
+
+use statistical::{ mean, standard_deviation };
+
+/// Two sided 95% critical values of the Student's t distribution, from 1 to 30 degrees of
+/// freedom. Beyond them the normal approximation is used.
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+    2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+    2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+const Z_95: f64 = 1.96;
+
+/// The mean of a metric over several runs, with its 95% confidence interval.
+#[derive(Debug, serde::Serialize)]
+pub struct Estimate {
+    pub metric: String,
+    pub runs: usize,
+    pub mean: f64,
+    pub std_dev: Option<f64>,
+    pub ci_95_low: Option<f64>,
+    pub ci_95_high: Option<f64>,
+}
+
+impl Estimate {
+    /// Estimates the mean of `metric` from its `samples`. The spread is only known with two
+    /// samples or more.
+    pub fn new(metric: &str, samples: &[f64]) -> Self {
+        let mean = mean(samples);
+        let (std_dev, margin) = if samples.len() > 1 {
+            let std_dev = standard_deviation(samples, Some(mean));
+            let critical_value = T_95.get(samples.len() - 2).copied().unwrap_or(Z_95);
+            (Some(std_dev), Some(critical_value * std_dev / (samples.len() as f64).sqrt()))
+        } else { (None, None) };
+
+        Estimate {
+            metric: metric.to_string(),
+            runs: samples.len(),
+            mean,
+            std_dev,
+            ci_95_low: margin.map(|margin| mean - margin),
+            ci_95_high: margin.map(|margin| mean + margin),
+        }
+    }
+}
+
+/// Saves the final summary metrics of each repeated run, keyed by their seed, to
+/// `repetitions.csv` and their estimates to `repetitions_report.csv`.
+pub fn write_report(output_path: &str, runs: &[(u64, SimulationSummary)]) -> Result<(), Box<dyn Error>> {
+    let names = match runs.first() {
+        Some((_, summary)) => summary.metrics().into_iter().map(|(name, _)| name).collect::<Vec<&str>>(),
+        None => return Err(From::from("there are no runs to report")),
+    };
+
+    let repetitions_file = OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(format!("{}/repetitions.csv", output_path))?;
+    let mut repetitions_writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(repetitions_file);
+
+    repetitions_writer.write_record(["run", "seed"].into_iter().chain(names.iter().copied()))?;
+    for (run, (seed, summary)) in runs.iter().enumerate() {
+        let record = [run.to_string(), seed.to_string()]
+            .into_iter()
+            .chain(summary.metrics().into_iter().map(|(_, value)| value.to_string()));
+        repetitions_writer.write_record(record)?;
+    }
+    repetitions_writer.flush()?;
+
+    let report_file = OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(format!("{}/repetitions_report.csv", output_path))?;
+    let mut report_writer = csv::WriterBuilder::new()
+        .has_headers(true)
+        .from_writer(report_file);
+
+    for (index, name) in names.iter().enumerate() {
+        let samples = runs
+            .iter()
+            .map(|(_, summary)| summary.metrics()[index].1)
+            .collect::<Vec<f64>>();
+        report_writer.serialize(Estimate::new(name, &samples))?;
+    }
+    report_writer.flush()?;
+
+    Ok(())
+}
+
+/// The SplitMix64 pseudorandom generator, good enough to resample and without pulling a
+/// dependency.
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// 95% percentile bootstrap confidence interval of the mean of `samples`, from `resamples`
+/// resamples of the same size drawn with replacement.
+pub fn bootstrap_mean_interval(samples: &[f64], resamples: usize, rng: &mut SplitMix64) -> Option<(f64, f64)> {
+    if samples.is_empty() || resamples == 0 {
+        return None;
+    }
+
+    let mut means = (0..resamples)
+        .map(|_| (0..samples.len()).map(|_| samples[rng.below(samples.len())]).sum::<f64>() / samples.len() as f64)
+        .collect::<Vec<f64>>();
+    means.sort_by(f64::total_cmp);
+
+    let percentile = |p: f64| means[((p * resamples as f64) as usize).min(resamples - 1)];
+    Some((percentile(0.025), percentile(0.975)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_single_run_has_no_interval() {
+        let estimate = Estimate::new("fees", &[5.0]);
+        assert_eq!(estimate.mean, 5.0);
+        assert_eq!((estimate.std_dev, estimate.ci_95_low, estimate.ci_95_high), (None, None, None));
+    }
+
+    #[test]
+    fn small_runs_use_the_t_distribution() {
+        // One degree of freedom, with a standard deviation of √2 over √2 runs.
+        let estimate = Estimate::new("fees", &[1.0, 3.0]);
+        assert_eq!(estimate.mean, 2.0);
+        assert!((estimate.ci_95_low.unwrap() - (2.0 - 12.706)).abs() < 1e-9);
+        assert!((estimate.ci_95_high.unwrap() - (2.0 + 12.706)).abs() < 1e-9);
+
+        let samples = (0..31).map(|x| (x % 2) as f64).collect::<Vec<f64>>();
+        let estimate = Estimate::new("fees", &samples);
+        let margin = 2.042 * estimate.std_dev.unwrap() / 31f64.sqrt();
+        assert!((estimate.ci_95_high.unwrap() - estimate.mean - margin).abs() < 1e-9);
+    }
+
+    #[test]
+    fn large_runs_use_the_normal_approximation() {
+        let samples = (0..32).map(|x| (x % 2) as f64).collect::<Vec<f64>>();
+        let estimate = Estimate::new("fees", &samples);
+        let margin = Z_95 * estimate.std_dev.unwrap() / 32f64.sqrt();
+        assert!((estimate.mean - estimate.ci_95_low.unwrap() - margin).abs() < 1e-9);
+        assert!((estimate.ci_95_high.unwrap() - estimate.mean - margin).abs() < 1e-9);
+    }
+
+    #[test]
+    fn bootstrap_needs_samples_and_resamples() {
+        assert_eq!(bootstrap_mean_interval(&[], 100, &mut SplitMix64::new(0)), None);
+        assert_eq!(bootstrap_mean_interval(&[1.0, 2.0], 0, &mut SplitMix64::new(0)), None);
+    }
+
+    #[test]
+    fn bootstrap_of_constant_samples_is_a_point() {
+        assert_eq!(bootstrap_mean_interval(&[4.0; 10], 1000, &mut SplitMix64::new(0)), Some((4.0, 4.0)));
+    }
+
+    #[test]
+    fn bootstrap_brackets_the_mean() {
+        let samples = (0..50).map(|x| (x * x % 17) as f64).collect::<Vec<f64>>();
+        let (low, high) = bootstrap_mean_interval(&samples, 2000, &mut SplitMix64::new(3)).unwrap();
+        let mean = mean(&samples);
+        assert!(0.0 <= low && low < mean && mean < high && high <= 16.0, "{} {} {}", low, mean, high);
+
+        // The same seed draws the same resamples.
+        assert_eq!(bootstrap_mean_interval(&samples, 2000, &mut SplitMix64::new(3)), Some((low, high)));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::models::SimulationSummary;
+use crate::selectors::SelectorParams;
+use crate::stats::SplitMix64;
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::OpenOptions,
+    str::FromStr,
+};
+
+use tracing::{ Level, event };
+
+/// A selector parameter and the values it is tried with.
+#[derive(Debug)]
+pub struct Dimension {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+impl FromStr for Dimension {
+    type Err = String;
+
+    /// Parses `name=a,b,c`, trying the listed values, or `name=start:end:step`, trying the values
+    /// from `start` to `end`, both included, every `step`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, values) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `name=values` in `{}`", s))?;
+        if !SelectorParams::NAMES.contains(&name) {
+            return Err(format!("unknown selector parameter `{}`", name));
+        }
+
+        let bounds = values.split(':').collect::<Vec<&str>>();
+        let values: Vec<String> = match bounds[..] {
+            [start, end, step] => {
+                let parse = |bound: &str| bound
+                    .parse::<f64>()
+                    .map_err(|err| format!("invalid bound `{}` of {}: {}", bound, name, err));
+                let (start, end, step) = (parse(start)?, parse(end)?, parse(step)?);
+                if step.is_nan() || step <= 0.0 || end < start {
+                    return Err(format!("the range of {} must have a positive step and end after it starts", name));
+                }
+                // Counting the steps instead of accumulating them keeps rounding errors away.
+                let steps = ((end - start) / step + 1e-9).floor() as usize;
+                (0..=steps).map(|i| (start + i as f64 * step).to_string()).collect()
+            },
+            [_] => values.split(',').map(String::from).collect(),
+            _ => return Err(format!("expected a list or a `start:end:step` range for {}", name)),
+        };
+
+        // The values are checked up front, so a bad one doesn't stop the search after some trials
+        // were already run.
+        let mut scratch = SelectorParams::default();
+        for value in values.iter() {
+            scratch
+                .set(name, value)
+                .map_err(|err| format!("invalid value `{}` of {}: {}", value, name, err))?;
+        }
+
+        Ok(Dimension {
+            name: name.to_string(),
+            values,
+        })
+    }
+}
+
+/// The settings of one or more selector parameters to try, every combination of their values.
+#[derive(Debug)]
+pub struct ParameterSpace {
+    pub dimensions: Vec<Dimension>,
+}
+
+impl FromStr for ParameterSpace {
+    type Err = String;
+
+    /// Parses the dimensions separated by `;`, as in `long-term-feerate=5:50:5;dust-limit=294,526`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let dimensions = s
+            .split(';')
+            .filter(|x| !x.is_empty())
+            .map(Dimension::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if dimensions.is_empty() {
+            return Err(String::from("the parameter space has no dimensions"));
+        }
+        let mut names = HashSet::new();
+        if let Some(repeated) = dimensions.iter().find(|x| !names.insert(&x.name)) {
+            return Err(format!("{} is given more than once", repeated.name));
+        }
+
+        Ok(ParameterSpace { dimensions })
+    }
+}
+
+impl ParameterSpace {
+    /// Number of settings in the grid.
+    pub fn size(&self) -> usize {
+        self.dimensions.iter().fold(1, |size, x| size.saturating_mul(x.values.len()))
+    }
+
+    /// The setting at `index` of the grid, where the last dimension changes the fastest.
+    pub fn setting(&self, mut index: usize) -> Vec<(&str, &str)> {
+        let mut setting = Vec::with_capacity(self.dimensions.len());
+        for dimension in self.dimensions.iter().rev() {
+            setting.push((dimension.name.as_str(), dimension.values[index % dimension.values.len()].as_str()));
+            index /= dimension.values.len();
+        }
+        setting.reverse();
+        setting
+    }
+}
+
+/// How the settings of a parameter space are chosen.
+#[derive(Debug, Clone, Copy)]
+pub enum Search {
+    /// Every setting, in order.
+    Grid,
+    /// `trials` distinct settings drawn at random, for spaces too big to search whole.
+    Random { trials: usize, seed: u64 },
+}
+
+impl Search {
+    pub fn from_name(name: &str, trials: usize, seed: u64) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "grid" => Ok(Search::Grid),
+            "random" => Ok(Search::Random { trials, seed }),
+            _ => Err(format!("unknown search `{}`, expected grid or random", name).into()),
+        }
+    }
+
+    /// Grid indices of the settings to try from a space of `size` settings.
+    fn indices(&self, size: usize) -> Vec<usize> {
+        match *self {
+            Search::Grid => (0..size).collect(),
+            Search::Random { trials, seed } => {
+                let mut rng = SplitMix64::new(seed);
+                let mut drawn = HashSet::new();
+                let mut indices = Vec::new();
+                while indices.len() < trials.min(size) {
+                    let index = rng.below(size);
+                    if drawn.insert(index) {
+                        indices.push(index);
+                    }
+                }
+                indices
+            },
+        }
+    }
+}
+
+/// What a tuning minimizes: a summary metric plus a penalty, in sats, for each UTXO left in the
+/// wallet.
+#[derive(Debug)]
+pub struct TuningObjective {
+    pub metric: String,
+    pub utxo_penalty: f64,
+}
+
+impl TuningObjective {
+    pub fn new(metric: &str, utxo_penalty: f64) -> Result<Self, Box<dyn Error>> {
+        // Checked before any trial runs, rather than after the first one.
+        if !SimulationSummary::default().metrics().iter().any(|(name, _)| *name == metric) {
+            return Err(format!("unknown metric `{}` to optimize", metric).into());
+        }
+
+        Ok(TuningObjective {
+            metric: metric.to_string(),
+            utxo_penalty,
+        })
+    }
+
+    pub fn value(&self, summary: &SimulationSummary) -> Result<f64, Box<dyn Error>> {
+        let metric = summary
+            .metrics()
+            .into_iter()
+            .find(|(name, _)| *name == self.metric)
+            .map(|(_, value)| value)
+            .ok_or_else(|| format!("unknown metric `{}`", self.metric))?;
+
+        Ok(metric + self.utxo_penalty * summary.current_utxo_set_count as f64)
+    }
+}
+
+/// A setting of the parameter space and the summary of the simulation run with it.
+pub struct Trial {
+    pub setting: Vec<(String, String)>,
+    pub summary: SimulationSummary,
+}
+
+/// Runs `search` over `space`, each setting applied on top of `params` and simulated by `run` in
+/// a `trial_<i>` directory under `output_path`.
+pub fn run_trials<F>(space: &ParameterSpace, search: Search, params: &SelectorParams, output_path: &str, mut run: F) -> Result<Vec<Trial>, Box<dyn Error>>
+where
+    F: FnMut(&SelectorParams, &str) -> Result<SimulationSummary, Box<dyn Error>>,
+{
+    let indices = search.indices(space.size());
+    let mut trials = Vec::with_capacity(indices.len());
+
+    for (trial, index) in indices.into_iter().enumerate() {
+        let setting = space.setting(index);
+        let mut params = params.clone();
+        for (name, value) in setting.iter() {
+            params.set(name, value).map_err(|err| format!("invalid {} `{}`: {}", name, value, err))?;
+        }
+        event!(Level::INFO, "trial {}: {:?}", trial, setting);
+
+        let summary = run(&params, &format!("{}/trial_{}", output_path, trial))?;
+        trials.push(Trial {
+            setting: setting.into_iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            summary,
+        });
+    }
+
+    Ok(trials)
+}
+
+/// Saves to `path` a row for each of the `trials`, with its setting of `space`, its `objectives`
+/// if any and its final summary metrics.
+fn write_trials(path: &str, space: &ParameterSpace, trials: &[Trial], objectives: Option<&[f64]>) -> Result<(), Box<dyn Error>> {
+    let names = match trials.first() {
+        Some(trial) => trial.summary.metrics().into_iter().map(|(name, _)| name).collect::<Vec<&str>>(),
+        None => return Err(From::from("there are no trials to save")),
+    };
+
+    let file = OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(path)?;
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(file);
+
+    writer.write_record(
+        ["trial"]
+            .into_iter()
+            .chain(space.dimensions.iter().map(|x| x.name.as_str()))
+            .chain(objectives.map(|_| "objective"))
+            .chain(names)
+    )?;
+    for (index, trial) in trials.iter().enumerate() {
+        let record = [index.to_string()]
+            .into_iter()
+            .chain(trial.setting.iter().map(|(_, value)| value.clone()))
+            .chain(objectives.map(|x| x[index].to_string()))
+            .chain(trial.summary.metrics().into_iter().map(|(_, value)| value.to_string()));
+        writer.write_record(record)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Looks for the setting of `space` minimizing `objective`, saving every trial and its summary
+/// metrics to `trials.csv` and printing the best one.
+pub fn tune<F>(space: &ParameterSpace, search: Search, objective: &TuningObjective, params: &SelectorParams, output_path: &str, run: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&SelectorParams, &str) -> Result<SimulationSummary, Box<dyn Error>>,
+{
+    let trials = run_trials(space, search, params, output_path, run)?;
+    let values = trials
+        .iter()
+        .map(|trial| objective.value(&trial.summary))
+        .collect::<Result<Vec<f64>, _>>()?;
+    write_trials(&format!("{}/trials.csv", output_path), space, &trials, Some(&values))?;
+
+    // Trials with an undefined objective, as a mean over no withdrawals, can't be the best.
+    let best = values
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| !value.is_nan())
+        .min_by(|a, b| a.1.total_cmp(b.1))
+        .ok_or("no trial has a defined objective")?;
+
+    let options = trials[best.0]
+        .setting
+        .iter()
+        .map(|(name, value)| format!("--{} {}", name, value))
+        .collect::<Vec<String>>();
+    println!("trials: {}", trials.len());
+    println!("best trial: {} with objective {}", best.0, best.1);
+    println!("best configuration: {}", options.join(" "));
+
+    Ok(())
+}
+
+/// Simulates every setting of `space`, of one or two parameters, saving their final summary
+/// metrics to `sweep.csv` to plot how sensitive they are to the swept parameters.
+pub fn sweep<F>(space: &ParameterSpace, params: &SelectorParams, output_path: &str, run: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&SelectorParams, &str) -> Result<SimulationSummary, Box<dyn Error>>,
+{
+    if space.dimensions.len() > 2 {
+        return Err(From::from("a sweep takes one or two parameters, use tune to search more"));
+    }
+
+    let trials = run_trials(space, Search::Grid, params, output_path, run)?;
+    write_trials(&format!("{}/sweep.csv", output_path), space, &trials, None)?;
+    println!("settings: {}", trials.len());
+
+    Ok(())
+}
@@ -0,0 +1,899 @@
+use crate::{ SEGWIT_V1_TXIN_WEIGHT, SEGWIT_V1_TXOUT_WEIGHT };
+use crate::models::{ PendingPayment, ScenarioEntry };
+use crate::selectors::pool;
+
+use std::{
+    cmp::Reverse,
+    collections::{ HashMap, HashSet },
+    error::Error,
+    fmt,
+    fs::File,
+    str::FromStr,
+};
+
+/// How the scenario rows are mapped to blocks.
+#[derive(Debug, Clone, Copy)]
+pub enum BlockModel {
+    /// The block height is given by the fourth column of the scenario.
+    Column,
+    /// Every block holds this many consecutive scenario rows.
+    RowsPerBlock(usize),
+}
+
+impl FromStr for BlockModel {
+    type Err = String;
+
+    /// Parses `column`, or the number of rows per block.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "column" => Ok(BlockModel::Column),
+            _ => match s.parse::<usize>() {
+                Ok(rows) if rows > 0 => Ok(BlockModel::RowsPerBlock(rows)),
+                _ => Err(format!("expected `column` or a positive number of rows per block, got `{}`", s)),
+            },
+        }
+    }
+}
+
+impl BlockModel {
+    /// The height of the block in which the scenario row at `position` happens.
+    pub fn height(&self, position: usize, record: &ScenarioEntry) -> Result<u64, Box<dyn Error>> {
+        match *self {
+            BlockModel::Column => record.block.ok_or_else(|| format!("scenario row {} has no block", position).into()),
+            BlockModel::RowsPerBlock(rows) => Ok((position / rows) as u64),
+        }
+    }
+}
+
+/// The feerates the transactions must pay to be included in each block.
+#[derive(Debug, Clone)]
+pub enum InclusionFeerates {
+    /// The feerate of the first scenario row of the next block, where the fees moved to while
+    /// the block was being mined.
+    Scenario,
+    /// A series of feerates by block height, in BTC per kvB as the scenario ones. Blocks missing
+    /// from it include every transaction.
+    Series(HashMap<u64, f32>),
+}
+
+impl InclusionFeerates {
+    /// Parses `scenario`, or reads the series from the file at `path`, of `block,feerate` rows.
+    pub fn from_arg(arg: &str) -> Result<Self, Box<dyn Error>> {
+        if arg == "scenario" {
+            return Ok(InclusionFeerates::Scenario);
+        }
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(File::open(arg)?);
+        let series = reader
+            .deserialize()
+            .collect::<Result<HashMap<u64, f32>, _>>()?;
+
+        Ok(InclusionFeerates::Series(series))
+    }
+
+    /// The feerate to be included in the block at `height`, found when the scenario reached
+    /// `record`, in the next block.
+    fn feerate(&self, height: u64, record: &ScenarioEntry) -> f32 {
+        match self {
+            InclusionFeerates::Scenario => record.fee_rate_per_kvb,
+            InclusionFeerates::Series(series) => series.get(&height).copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// Which UTxOs may be spent, as the coin eligibility filters of Bitcoin Core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EligibilityFilter {
+    /// Confirmations needed by the change of the wallet's own transactions.
+    pub conf_mine: u64,
+    /// Confirmations needed by the UTxOs received from others.
+    pub conf_theirs: u64,
+    /// Most unconfirmed transactions a UTxO may descend from, counting the one creating it, or
+    /// any number if not given.
+    pub max_ancestors: Option<usize>,
+}
+
+impl EligibilityFilter {
+    /// A single filter asking every UTxO for `confirmations`.
+    pub fn confirmations(confirmations: u64) -> Self {
+        EligibilityFilter {
+            conf_mine: confirmations,
+            conf_theirs: confirmations,
+            max_ancestors: None,
+        }
+    }
+
+    /// The filters Bitcoin Core tries in turn: 6 confirmations, 1 for its own change, then 1
+    /// confirmation, then unconfirmed change with more and more ancestors up to `ancestor_limit`.
+    pub fn core_tiers(ancestor_limit: usize) -> Vec<Self> {
+        let tier = |conf_mine, conf_theirs, max_ancestors| EligibilityFilter {
+            conf_mine,
+            conf_theirs,
+            max_ancestors: Some(max_ancestors),
+        };
+        let mut tiers = vec![
+            tier(1, 6, 0),
+            tier(1, 1, 0),
+            tier(0, 1, 2),
+            tier(0, 1, usize::min(4, ancestor_limit / 3)),
+            tier(0, 1, ancestor_limit / 2),
+            tier(0, 1, ancestor_limit.saturating_sub(1)),
+        ];
+        tiers.dedup();
+        tiers
+    }
+}
+
+impl fmt::Display for EligibilityFilter {
+    /// Shown as `conf_mine/conf_theirs/max_ancestors`, as in `1/6/0`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.max_ancestors {
+            Some(max_ancestors) => write!(f, "{}/{}/{}", self.conf_mine, self.conf_theirs, max_ancestors),
+            None => write!(f, "{}/{}/any", self.conf_mine, self.conf_theirs),
+        }
+    }
+}
+
+/// The limits the mempool policy puts on chains of unconfirmed transactions, by default the
+/// ones of Bitcoin Core.
+#[derive(Debug, Clone, Copy)]
+pub struct ChainLimits {
+    /// Most unconfirmed transactions a transaction may descend from, counting itself.
+    pub ancestor_count: usize,
+    /// Most unconfirmed transactions which may descend from a transaction, counting itself.
+    pub descendant_count: usize,
+    /// Most vbytes of a transaction and its unconfirmed ancestors.
+    pub ancestor_size: u64,
+    /// Most vbytes of a transaction and its unconfirmed descendants.
+    pub descendant_size: u64,
+}
+
+impl Default for ChainLimits {
+    fn default() -> ChainLimits {
+        ChainLimits {
+            ancestor_count: 25,
+            descendant_count: 25,
+            ancestor_size: 101_000,
+            descendant_size: 101_000,
+        }
+    }
+}
+
+/// Size in vbytes taken for the received transactions, of one input and two outputs.
+const RECEIVED_SIZE: u64 = 4 + 4 + 1 + 1 + SEGWIT_V1_TXIN_WEIGHT as u64 + 2 * SEGWIT_V1_TXOUT_WEIGHT as u64;
+
+/// The unconfirmed transactions each unconfirmed transaction descends from, and the ones
+/// descending from it, both counting itself.
+#[derive(Debug)]
+struct Chains {
+    ancestors: HashMap<usize, HashSet<usize>>,
+    descendants: HashMap<usize, HashSet<usize>>,
+}
+
+/// A wallet UTxO and the block which confirmed it, if any.
+#[derive(Debug, Clone)]
+pub struct Utxo {
+    pub value: u64,
+    pub confirmed: Option<u64>,
+    /// Whether it's the change of a wallet transaction, rather than received from others.
+    pub change: bool,
+    /// The unconfirmed transaction which created it.
+    tx: Option<usize>,
+}
+
+impl Utxo {
+    /// Confirmations of the UTxO while the block at `height` is being mined.
+    pub fn confirmations(&self, height: u64) -> u64 {
+        self.confirmed.map_or(0, |x| height.saturating_sub(x))
+    }
+}
+
+/// A transaction broadcast by the wallet.
+#[derive(Debug, Clone, Default)]
+pub struct Transaction {
+    /// Size in vbytes.
+    pub size: u64,
+    pub fee: u64,
+    pub payments: Vec<PendingPayment>,
+}
+
+impl Transaction {
+    /// Whether a replacement of `size` vbytes paying `fee` pays enough to replace it, as in the
+    /// rules of Bitcoin Core: more fees than it, at least 1 sat/vB of its own size more.
+    pub fn replaceable_by(&self, fee: u64, size: u64) -> bool {
+        fee >= self.fee + size
+    }
+}
+
+/// An unconfirmed transaction made or received by the wallet.
+#[derive(Debug, Clone)]
+struct MempoolTransaction {
+    /// The unconfirmed transactions it spends from.
+    parents: Vec<usize>,
+    /// The transaction, or `None` when it was received and its fee is unknown, in which case it
+    /// is always included in the next block.
+    transaction: Option<Transaction>,
+    /// The UTxOs it spent, to get them back if it's replaced.
+    inputs: Vec<Utxo>,
+}
+
+/// The UTxOs given to the selector by an eligibility filter.
+#[derive(Debug, Default)]
+pub struct Spendable {
+    /// Indices of the UTxOs in the wallet.
+    pub candidates: Vec<usize>,
+    pub values: Vec<u64>,
+    /// Values of the UTxOs allowed by the filter but left out because of the chain limits.
+    pub chain_limited: Vec<u64>,
+}
+
+/// The UTxOs of the simulated wallet and their confirmations, as the selectors only see the
+/// UTxOs they are allowed to spend.
+///
+/// The transactions made or received while a block is being mined are confirmed by it, so their
+/// outputs have a confirmation once the scenario reaches the next block. With the inclusion
+/// feerates of the blocks, only the wallet transactions paying them are.
+#[derive(Debug, Clone)]
+pub struct Wallet {
+    pub blocks: BlockModel,
+    /// The filters of the UTxOs given to the selectors, tried in turn until one of them lets
+    /// the selector fund the payments.
+    pub tiers: Vec<EligibilityFilter>,
+    pub limits: ChainLimits,
+    pub inclusion: Option<InclusionFeerates>,
+    pub long_term_feerate: f32,
+    /// The block being mined, unknown before the first row.
+    height: Option<u64>,
+    /// The inclusion feerate of the last block found, in BTC per kvB.
+    last_inclusion_feerate: Option<f32>,
+    utxos: Vec<Utxo>,
+    mempool: HashMap<usize, MempoolTransaction>,
+    next_tx: usize,
+}
+
+impl Wallet {
+    pub fn new(blocks: BlockModel, tiers: Vec<EligibilityFilter>, limits: ChainLimits, inclusion: Option<InclusionFeerates>, long_term_feerate: f32) -> Self {
+        Wallet {
+            blocks,
+            tiers,
+            limits,
+            inclusion,
+            long_term_feerate,
+            height: None,
+            last_inclusion_feerate: None,
+            utxos: Vec::new(),
+            mempool: HashMap::new(),
+            next_tx: 0,
+        }
+    }
+
+    /// Moves to the block of the scenario row at `position`, mining the block being mined
+    /// until then if the row is in a later one. Returns whether a block was found.
+    pub fn advance(&mut self, position: usize, record: &ScenarioEntry) -> Result<bool, Box<dyn Error>> {
+        let height = self.blocks.height(position, record)?;
+        let found = match self.height {
+            Some(current) if height < current => {
+                return Err(format!("scenario row {} is in block {}, before block {}", position, height, current).into());
+            },
+            Some(current) if height > current => {
+                let feerate = self.inclusion.as_ref().map(|x| x.feerate(current, record));
+                self.mine(current, feerate);
+                true
+            },
+            _ => false,
+        };
+        self.height = Some(height);
+
+        Ok(found)
+    }
+
+    /// Confirms in the block at `height` the unconfirmed transactions whose package, with their
+    /// unconfirmed ancestors, pays at least `feerate` BTC per kvB, or all of them without it.
+    fn mine(&mut self, height: u64, feerate: Option<f32>) {
+        let mut txs = self.mempool.keys().copied().collect::<Vec<usize>>();
+        // Parents are broadcast before their children, so they are mined first if they pay
+        // enough by themselves, and otherwise along with a child paying for them.
+        txs.sort_unstable();
+
+        let mut mined = HashSet::new();
+        for tx in txs {
+            if mined.contains(&tx) {
+                continue;
+            }
+            let package = self
+                .ancestors(tx)
+                .into_iter()
+                .filter(|x| !mined.contains(x))
+                .collect::<HashSet<usize>>();
+            let included = match (feerate, self.mempool[&tx].transaction.as_ref()) {
+                (Some(feerate), Some(_)) => {
+                    let (fee, size) = self.package(&package);
+                    fee as f64 >= pool::sat_per_vb(feerate) * size as f64
+                },
+                _ => true,
+            };
+            if included {
+                mined.extend(package);
+            }
+        }
+
+        let confirm = |utxo: &mut Utxo| {
+            if utxo.tx.is_some_and(|x| mined.contains(&x)) {
+                utxo.confirmed = Some(height);
+                utxo.tx = None;
+            }
+        };
+        self.utxos.iter_mut().for_each(confirm);
+        self.mempool.retain(|tx, _| !mined.contains(tx));
+        for transaction in self.mempool.values_mut() {
+            transaction.parents.retain(|x| !mined.contains(x));
+            transaction.inputs.iter_mut().for_each(confirm);
+        }
+        self.last_inclusion_feerate = feerate;
+    }
+
+    /// The inclusion feerate of the last block found, in BTC per kvB, when they're simulated.
+    pub fn last_inclusion_feerate(&self) -> Option<f32> {
+        self.last_inclusion_feerate
+    }
+
+    /// Adds an unconfirmed `transaction` spending the `inputs` created by the unconfirmed
+    /// `parents`, returning its id.
+    fn broadcast(&mut self, parents: Vec<usize>, transaction: Option<Transaction>, inputs: Vec<Utxo>) -> usize {
+        let tx = self.next_tx;
+        self.next_tx += 1;
+        self.mempool.insert(tx, MempoolTransaction { parents, transaction, inputs });
+        tx
+    }
+
+    /// The fee and size of the wallet transactions among `txs`.
+    fn package(&self, txs: &HashSet<usize>) -> (u64, u64) {
+        txs.iter()
+            .filter_map(|x| self.mempool[x].transaction.as_ref())
+            .fold((0, 0), |(fee, size), x| (fee + x.fee, size + x.size))
+    }
+
+    /// The fee and size of the unconfirmed wallet transaction `tx` and its unconfirmed
+    /// ancestors.
+    fn ancestor_package(&self, tx: usize) -> (u64, u64) {
+        self.package(&self.ancestors(tx))
+    }
+
+    /// The fee a child of `size` vbytes spending from the unconfirmed `tx` must pay for its own
+    /// size and whatever its ancestors miss to reach `fee_rate_per_kvb`.
+    pub fn child_fee(&self, tx: usize, size: u64, fee_rate_per_kvb: f32) -> u64 {
+        let (package_fee, package_size) = self.ancestor_package(tx);
+        ((pool::sat_per_vb(fee_rate_per_kvb) * (package_size + size) as f64).ceil() as u64).saturating_sub(package_fee)
+    }
+
+    /// The unconfirmed wallet transactions without unconfirmed children, which missed a block
+    /// when found right after it.
+    pub fn stuck(&self) -> Vec<usize> {
+        let parents = self.mempool
+            .values()
+            .flat_map(|x| x.parents.iter().copied())
+            .collect::<HashSet<usize>>();
+        let mut stuck = self.mempool
+            .iter()
+            .filter(|(tx, transaction)| transaction.transaction.is_some() && !parents.contains(tx))
+            .map(|(tx, _)| *tx)
+            .collect::<Vec<usize>>();
+        stuck.sort_unstable();
+        stuck
+    }
+
+    /// Whether `tx` is still unconfirmed and without unconfirmed children, so it can be bumped.
+    pub fn is_stuck(&self, tx: usize) -> bool {
+        self.mempool.get(&tx).is_some_and(|x| x.transaction.is_some())
+            && self.mempool.values().all(|x| !x.parents.contains(&tx))
+    }
+
+    /// Index of the change UTxO created by the unconfirmed `tx`, if it has one.
+    pub fn change_of(&self, tx: usize) -> Option<usize> {
+        self.utxos.iter().position(|x| x.change && x.tx == Some(tx))
+    }
+
+    pub fn value(&self, index: usize) -> u64 {
+        self.utxos[index].value
+    }
+
+    /// Takes the unconfirmed wallet transaction `tx` out of the mempool to replace it, removing
+    /// its change and getting back the UTxOs it spent. Returns the transaction and the indices
+    /// of its inputs in the wallet.
+    pub fn unbroadcast(&mut self, tx: usize) -> Result<(Transaction, Vec<usize>), Box<dyn Error>> {
+        if !self.is_stuck(tx) {
+            return Err(format!("transaction {} can't be replaced", tx).into());
+        }
+        let removed = self.mempool.remove(&tx).expect("checked above");
+
+        self.utxos.retain(|x| x.tx != Some(tx));
+        let start = self.utxos.len();
+        self.utxos.extend(removed.inputs);
+
+        Ok((removed.transaction.expect("checked above"), (start..self.utxos.len()).collect()))
+    }
+
+    /// The unconfirmed transactions `tx` descends from, itself included.
+    fn ancestors(&self, tx: usize) -> HashSet<usize> {
+        let mut ancestors = HashSet::new();
+        let mut pending = vec![tx];
+        while let Some(tx) = pending.pop() {
+            if ancestors.insert(tx) {
+                pending.extend(self.mempool.get(&tx).into_iter().flat_map(|x| x.parents.iter()));
+            }
+        }
+        ancestors
+    }
+
+    /// The ancestors and descendants of every unconfirmed transaction.
+    fn chains(&self) -> Chains {
+        let ancestors = self.mempool
+            .keys()
+            .map(|tx| (*tx, self.ancestors(*tx)))
+            .collect::<HashMap<usize, HashSet<usize>>>();
+        let mut descendants = HashMap::<usize, HashSet<usize>>::new();
+        for (tx, tx_ancestors) in ancestors.iter() {
+            for ancestor in tx_ancestors {
+                descendants.entry(*ancestor).or_default().insert(*tx);
+            }
+        }
+
+        Chains { ancestors, descendants }
+    }
+
+    fn size(&self, txs: &HashSet<usize>) -> u64 {
+        txs.iter().map(|x| self.mempool[x].transaction.as_ref().map_or(RECEIVED_SIZE, |x| x.size)).sum()
+    }
+
+    fn within_chain_limits(&self, chains: &Chains, parents: &[usize], size: u64) -> bool {
+        let ancestors = parents
+            .iter()
+            .flat_map(|x| chains.ancestors[x].iter().copied())
+            .collect::<HashSet<usize>>();
+        if ancestors.len() + 1 > self.limits.ancestor_count || self.size(&ancestors) + size > self.limits.ancestor_size {
+            return false;
+        }
+
+        ancestors.iter().all(|ancestor| {
+            let descendants = &chains.descendants[ancestor];
+            descendants.len() < self.limits.descendant_count && self.size(descendants) + size <= self.limits.descendant_size
+        })
+    }
+
+    /// Whether a transaction of `size` vbytes spending from the unconfirmed `parents` keeps its
+    /// chain within the limits.
+    pub fn within_limits(&self, parents: &[usize], size: u64) -> bool {
+        self.within_chain_limits(&self.chains(), parents, size)
+    }
+
+    /// Adds an unconfirmed UTxO worth `value` received from others. The ancestors of the
+    /// transaction paying it are unknown, so it's taken as their only one.
+    pub fn receive(&mut self, value: u64) {
+        let tx = self.broadcast(Vec::new(), None, Vec::new());
+        self.utxos.push(Utxo {
+            value,
+            confirmed: None,
+            change: false,
+            tx: Some(tx),
+        });
+    }
+
+    /// The UTxOs allowed by `filter` which can be spent within the chain limits, at least by a
+    /// transaction of one input and one output.
+    pub fn eligible(&self, filter: &EligibilityFilter) -> Spendable {
+        let height = self.height.unwrap_or_default();
+        let smallest_spend = (pool::base_weight(&[]) + SEGWIT_V1_TXIN_WEIGHT + SEGWIT_V1_TXOUT_WEIGHT) as u64;
+
+        let chains = self.chains();
+        let mut spendable = Spendable::default();
+        for (index, utxo) in self.utxos.iter().enumerate() {
+            let confirmations = if utxo.change { filter.conf_mine } else { filter.conf_theirs };
+            let ancestors = utxo.tx.map_or(0, |tx| chains.ancestors[&tx].len());
+            if utxo.confirmations(height) < confirmations || filter.max_ancestors.is_some_and(|x| ancestors > x) {
+                continue;
+            }
+
+            if utxo.tx.is_some_and(|tx| !self.within_chain_limits(&chains, &[tx], smallest_spend)) {
+                spendable.chain_limited.push(utxo.value);
+                continue;
+            }
+            spendable.candidates.push(index);
+            spendable.values.push(utxo.value);
+        }
+
+        spendable
+    }
+
+    /// Indices of the UTxOs spent as `inputs`, out of the `candidates` given to the selector.
+    /// UTxOs of the same value can't be told apart by the selector, so the one with the fewest
+    /// unconfirmed ancestors and then the most confirmations is taken, the first of the
+    /// `candidates` on a tie.
+    pub fn spent(&self, candidates: &[usize], inputs: &[u64]) -> Result<Vec<usize>, Box<dyn Error>> {
+        let height = self.height.unwrap_or_default();
+        let chains = self.chains();
+        let preference = |index: &usize| {
+            let utxo = &self.utxos[*index];
+            (utxo.tx.map_or(0, |tx| chains.ancestors[&tx].len()), Reverse(utxo.confirmations(height)))
+        };
+
+        let mut spent = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let index = candidates
+                .iter()
+                .copied()
+                .filter(|index| self.utxos[*index].value == *input && !spent.contains(index))
+                .min_by_key(preference)
+                .ok_or_else(|| format!("the spent input of {} sats wasn't given to the selector", input))?;
+            spent.push(index);
+        }
+
+        Ok(spent)
+    }
+
+    /// The unconfirmed transactions a transaction spending `inputs` out of `candidates` would
+    /// spend from.
+    pub fn parents(&self, candidates: &[usize], inputs: &[u64]) -> Result<Vec<usize>, Box<dyn Error>> {
+        let mut parents = self
+            .spent(candidates, inputs)?
+            .into_iter()
+            .filter_map(|x| self.utxos[x].tx)
+            .collect::<Vec<usize>>();
+        parents.sort_unstable();
+        parents.dedup();
+
+        Ok(parents)
+    }
+
+    /// Broadcasts `transaction`, spending `inputs` out of the `candidates` given to the
+    /// selector and creating `change`.
+    pub fn spend(&mut self, candidates: &[usize], inputs: &[u64], change: Option<u64>, transaction: Transaction) -> Result<(), Box<dyn Error>> {
+        let parents = self.parents(candidates, inputs)?;
+        let spent = self.spent(candidates, inputs)?;
+        let spent_utxos = spent.iter().map(|x| self.utxos[*x].clone()).collect();
+        let tx = self.broadcast(parents, Some(transaction), spent_utxos);
+
+        let mut index = 0;
+        self.utxos.retain(|_| {
+            index += 1;
+            !spent.contains(&(index - 1))
+        });
+        if let Some(change) = change {
+            self.utxos.push(Utxo {
+                value: change,
+                confirmed: None,
+                change: true,
+                tx: Some(tx),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn utxo_count(&self) -> usize {
+        self.utxos.len()
+    }
+
+    pub fn balance(&self) -> u64 {
+        self.utxos.iter().map(|x| x.value).sum()
+    }
+
+    pub fn cost_to_empty_at_long_term_feerate(&self) -> f32 {
+        self.utxos.len() as f32 * SEGWIT_V1_TXIN_WEIGHT as f32 * self.long_term_feerate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(fee_rate_per_kvb: f32) -> ScenarioEntry {
+        ScenarioEntry {
+            amount: 0.0,
+            fee_rate_per_kvb,
+            urgency: None,
+            block: None,
+        }
+    }
+
+    fn wallet(tiers: Vec<EligibilityFilter>) -> Wallet {
+        Wallet::new(BlockModel::RowsPerBlock(1), tiers, ChainLimits::default(), None, 10.0)
+    }
+
+    fn transaction(size: u64, fee: u64) -> Transaction {
+        Transaction {
+            size,
+            fee,
+            payments: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn rows_are_mapped_to_blocks() {
+        let rows = "3".parse::<BlockModel>().unwrap();
+        assert_eq!(rows.height(7, &row(0.0)).unwrap(), 2);
+
+        let column = "column".parse::<BlockModel>().unwrap();
+        assert_eq!(column.height(0, &ScenarioEntry { block: Some(5), ..row(0.0) }).unwrap(), 5);
+        assert!(column.height(0, &row(0.0)).is_err());
+
+        assert!("0".parse::<BlockModel>().is_err());
+    }
+
+    #[test]
+    fn blocks_can_not_go_back() {
+        let mut wallet = Wallet::new(BlockModel::Column, vec![EligibilityFilter::confirmations(1)], ChainLimits::default(), None, 10.0);
+        assert!(!wallet.advance(0, &ScenarioEntry { block: Some(3), ..row(0.0) }).unwrap());
+        assert!(wallet.advance(1, &ScenarioEntry { block: Some(4), ..row(0.0) }).unwrap());
+        assert!(wallet.advance(2, &ScenarioEntry { block: Some(2), ..row(0.0) }).is_err());
+    }
+
+    #[test]
+    fn utxos_need_confirmations_to_be_spent() {
+        let mut wallet = wallet(vec![EligibilityFilter::confirmations(2)]);
+        wallet.advance(0, &row(0.0)).unwrap();
+        wallet.receive(10_000);
+        assert!(wallet.eligible(&wallet.tiers[0]).values.is_empty());
+
+        // Confirmed by block 0, it has one confirmation while block 1 is mined and two after.
+        wallet.advance(1, &row(0.0)).unwrap();
+        assert!(wallet.eligible(&wallet.tiers[0]).values.is_empty());
+        wallet.advance(2, &row(0.0)).unwrap();
+        assert_eq!(wallet.eligible(&wallet.tiers[0]).values, vec![10_000]);
+
+        assert!(wallet.eligible(&EligibilityFilter::confirmations(0)).values.contains(&10_000));
+    }
+
+    #[test]
+    fn spent_utxos_are_replaced_by_their_unconfirmed_change() {
+        let mut wallet = wallet(vec![EligibilityFilter::confirmations(1)]);
+        wallet.advance(0, &row(0.0)).unwrap();
+        wallet.receive(10_000);
+        wallet.receive(20_000);
+        wallet.advance(1, &row(0.0)).unwrap();
+
+        let spendable = wallet.eligible(&wallet.tiers[0]);
+        wallet.spend(&spendable.candidates, &[20_000], Some(5_000), transaction(150, 1_000)).unwrap();
+        assert_eq!(wallet.utxo_count(), 2);
+        assert_eq!(wallet.balance(), 15_000);
+        assert_eq!(wallet.eligible(&wallet.tiers[0]).values, vec![10_000]);
+
+        wallet.advance(2, &row(0.0)).unwrap();
+        assert_eq!(wallet.eligible(&wallet.tiers[0]).values, vec![10_000, 5_000]);
+    }
+
+    #[test]
+    fn equal_inputs_spend_the_confirmed_utxo() {
+        let series = HashMap::from([(0, 1e-4), (1, 1e-4), (2, 1e-4)]);
+        let mut wallet = Wallet::new(BlockModel::RowsPerBlock(1), vec![EligibilityFilter::confirmations(0)], ChainLimits::default(), Some(InclusionFeerates::Series(series)), 10.0);
+        wallet.advance(0, &row(0.0)).unwrap();
+        wallet.receive(20_000);
+        wallet.advance(1, &row(0.0)).unwrap();
+
+        // The change of a transaction paying 5 sat/vB stays unconfirmed, ahead of a confirmed
+        // UTxO of the same value.
+        let spendable = wallet.eligible(&wallet.tiers[0]);
+        wallet.spend(&spendable.candidates, &[20_000], Some(8_000), transaction(100, 500)).unwrap();
+        wallet.receive(8_000);
+        wallet.advance(2, &row(0.0)).unwrap();
+
+        let spendable = wallet.eligible(&wallet.tiers[0]);
+        assert_eq!(spendable.values, vec![8_000, 8_000]);
+        assert!(wallet.parents(&spendable.candidates, &[8_000]).unwrap().is_empty());
+        wallet.spend(&spendable.candidates, &[8_000], None, transaction(100, 1_000)).unwrap();
+        assert_eq!(wallet.utxo_count(), 1);
+        assert!(wallet.eligible(&EligibilityFilter::confirmations(1)).values.is_empty());
+    }
+
+    #[test]
+    fn core_tiers_go_from_the_strictest_to_the_most_permissive() {
+        let tiers = EligibilityFilter::core_tiers(25)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>();
+        assert_eq!(tiers, ["1/6/0", "1/1/0", "0/1/2", "0/1/4", "0/1/12", "0/1/24"]);
+
+        // With low ancestor limits, a tier repeating the one before it is only tried once.
+        let tiers = EligibilityFilter::core_tiers(3)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>();
+        assert_eq!(tiers, ["1/6/0", "1/1/0", "0/1/2", "0/1/1", "0/1/2"]);
+
+        assert_eq!(EligibilityFilter::confirmations(3).to_string(), "3/3/any");
+    }
+
+    #[test]
+    fn tiers_tell_change_from_received_utxos() {
+        let mut wallet = wallet(EligibilityFilter::core_tiers(25));
+        wallet.advance(0, &row(0.0)).unwrap();
+        wallet.receive(10_000);
+        wallet.receive(20_000);
+        wallet.advance(1, &row(0.0)).unwrap();
+        assert!(wallet.eligible(&wallet.tiers[0]).values.is_empty());
+        assert_eq!(wallet.eligible(&wallet.tiers[1]).values, vec![10_000, 20_000]);
+
+        let spendable = wallet.eligible(&wallet.tiers[1]);
+        wallet.spend(&spendable.candidates, &[20_000], Some(5_000), transaction(150, 1_000)).unwrap();
+        wallet.receive(30_000);
+
+        // The unconfirmed change may be spent from the third tier, but not the UTxO received
+        // from others.
+        assert_eq!(wallet.eligible(&wallet.tiers[1]).values, vec![10_000]);
+        assert_eq!(wallet.eligible(&wallet.tiers[2]).values, vec![10_000, 5_000]);
+    }
+
+    #[test]
+    fn tiers_limit_the_unconfirmed_ancestors_of_change() {
+        let tier = |max_ancestors| EligibilityFilter {
+            conf_mine: 0,
+            conf_theirs: 1,
+            max_ancestors: Some(max_ancestors),
+        };
+        let mut wallet = wallet(vec![tier(1)]);
+        wallet.advance(0, &row(0.0)).unwrap();
+        wallet.receive(10_000);
+        wallet.advance(1, &row(0.0)).unwrap();
+
+        let spendable = wallet.eligible(&tier(1));
+        wallet.spend(&spendable.candidates, &[10_000], Some(8_000), transaction(150, 1_000)).unwrap();
+        let spendable = wallet.eligible(&tier(1));
+        wallet.spend(&spendable.candidates, &[8_000], Some(6_000), transaction(150, 1_000)).unwrap();
+
+        assert!(wallet.eligible(&tier(1)).values.is_empty());
+        assert_eq!(wallet.eligible(&tier(2)).values, vec![6_000]);
+    }
+
+    /// Broadcasts a chain of `length` unconfirmed transactions of `size` vbytes each spending
+    /// from the one before, returning the last one.
+    fn chain(wallet: &mut Wallet, length: usize, size: u64) -> usize {
+        let mut tx = wallet.broadcast(Vec::new(), Some(transaction(size, 0)), Vec::new());
+        for _ in 1..length {
+            tx = wallet.broadcast(vec![tx], Some(transaction(size, 0)), Vec::new());
+        }
+        tx
+    }
+
+    #[test]
+    fn ancestor_count_limit() {
+        let mut wallet = wallet(vec![EligibilityFilter::confirmations(0)]);
+        let tx = chain(&mut wallet, 24, 100);
+        assert!(wallet.within_limits(&[tx], 100));
+
+        let tx = wallet.broadcast(vec![tx], Some(transaction(100, 0)), Vec::new());
+        assert!(!wallet.within_limits(&[tx], 100));
+    }
+
+    #[test]
+    fn descendant_count_limit() {
+        let mut wallet = wallet(vec![EligibilityFilter::confirmations(0)]);
+        let root = chain(&mut wallet, 1, 100);
+        for _ in 0..23 {
+            wallet.broadcast(vec![root], Some(transaction(100, 0)), Vec::new());
+        }
+        assert!(wallet.within_limits(&[root], 100));
+
+        wallet.broadcast(vec![root], Some(transaction(100, 0)), Vec::new());
+        assert!(!wallet.within_limits(&[root], 100));
+    }
+
+    #[test]
+    fn ancestor_size_limit() {
+        let mut wallet = wallet(vec![EligibilityFilter::confirmations(0)]);
+        let tx = chain(&mut wallet, 2, 50_000);
+        assert!(wallet.within_limits(&[tx], 1_000));
+        assert!(!wallet.within_limits(&[tx], 1_001));
+    }
+
+    #[test]
+    fn descendant_size_limit() {
+        let mut wallet = wallet(vec![EligibilityFilter::confirmations(0)]);
+        let root = chain(&mut wallet, 1, 1_000);
+        // The descendants of the root weigh 100 kvB, but each of them has a single ancestor.
+        for _ in 0..2 {
+            wallet.broadcast(vec![root], Some(transaction(49_500, 0)), Vec::new());
+        }
+        assert!(wallet.within_limits(&[root], 1_000));
+        assert!(!wallet.within_limits(&[root], 1_001));
+    }
+
+    #[test]
+    fn received_transactions_count_in_the_limits() {
+        let mut wallet = wallet(vec![EligibilityFilter::confirmations(0)]);
+        wallet.limits.ancestor_count = 2;
+        wallet.receive(10_000);
+        let spendable = wallet.eligible(&wallet.tiers[0]);
+        assert_eq!(spendable.values, vec![10_000]);
+
+        wallet.spend(&spendable.candidates, &[10_000], Some(8_000), transaction(150, 1_000)).unwrap();
+        let spendable = wallet.eligible(&wallet.tiers[0]);
+        assert!(spendable.values.is_empty());
+        assert_eq!(spendable.chain_limited, vec![8_000]);
+    }
+
+    #[test]
+    fn blocks_only_include_packages_paying_their_feerate() {
+        // 10 sat/vB for the first two blocks.
+        let series = HashMap::from([(0, 1e-4), (1, 1e-4)]);
+        let mut wallet = Wallet::new(BlockModel::RowsPerBlock(1), vec![EligibilityFilter::confirmations(1)], ChainLimits::default(), Some(InclusionFeerates::Series(series)), 10.0);
+        wallet.advance(0, &row(0.0)).unwrap();
+        wallet.receive(100_000);
+        let parent = wallet.broadcast(Vec::new(), Some(transaction(100, 500)), Vec::new());
+
+        // The received transaction is always included, the parent only pays 5 sat/vB.
+        assert!(wallet.advance(1, &row(0.0)).unwrap());
+        assert_eq!(wallet.last_inclusion_feerate(), Some(1e-4));
+        assert_eq!(wallet.eligible(&wallet.tiers[0]).values, vec![100_000]);
+        assert_eq!(wallet.stuck(), vec![parent]);
+
+        // A child paying 15 sat/vB gets both in at 10 sat/vB.
+        let child = wallet.broadcast(vec![parent], Some(transaction(100, 1_500)), Vec::new());
+        assert_eq!(wallet.stuck(), vec![child]);
+        assert!(!wallet.is_stuck(parent));
+        wallet.advance(2, &row(0.0)).unwrap();
+        assert!(wallet.stuck().is_empty());
+    }
+
+    #[test]
+    fn a_child_pays_for_its_unconfirmed_ancestors() {
+        let mut wallet = wallet(vec![EligibilityFilter::confirmations(0)]);
+        let grandparent = wallet.broadcast(Vec::new(), Some(transaction(100, 500)), Vec::new());
+        let parent = wallet.broadcast(vec![grandparent], Some(transaction(100, 500)), Vec::new());
+
+        let fee_rate_per_kvb = 1e-4;
+        let fee = wallet.child_fee(parent, 100, fee_rate_per_kvb);
+        let target = pool::sat_per_vb(fee_rate_per_kvb) * 300.0;
+        assert!((1_000 + fee) as f64 >= target);
+        assert!(((1_000 + fee - 1) as f64) < target);
+
+        // Ancestors paying more than the feerate don't lower the fee of the child under zero.
+        let rich = wallet.broadcast(Vec::new(), Some(transaction(100, 100_000)), Vec::new());
+        assert_eq!(wallet.child_fee(rich, 100, fee_rate_per_kvb), 0);
+    }
+
+    #[test]
+    fn replacements_follow_the_fee_rules() {
+        let original = transaction(200, 1_000);
+        // Paying less, or more but not for its own size, doesn't replace it.
+        assert!(!original.replaceable_by(900, 150));
+        assert!(!original.replaceable_by(1_149, 150));
+        assert!(original.replaceable_by(1_150, 150));
+    }
+
+    #[test]
+    fn replaced_transactions_give_their_inputs_back() {
+        let mut wallet = wallet(vec![EligibilityFilter::confirmations(0)]);
+        wallet.receive(10_000);
+        wallet.receive(20_000);
+        let spendable = wallet.eligible(&wallet.tiers[0]);
+        wallet.spend(&spendable.candidates, &[20_000], Some(15_000), transaction(150, 1_000)).unwrap();
+        let tx = wallet.stuck()[0];
+        assert_eq!(wallet.balance(), 25_000);
+
+        let (original, restored) = wallet.unbroadcast(tx).unwrap();
+        assert_eq!(original.fee, 1_000);
+        assert_eq!(restored.iter().map(|x| wallet.value(*x)).collect::<Vec<u64>>(), vec![20_000]);
+        assert_eq!(wallet.balance(), 30_000);
+
+        // Transactions with unconfirmed children can't be replaced.
+        let spendable = wallet.eligible(&wallet.tiers[0]);
+        wallet.spend(&spendable.candidates, &[20_000], Some(15_000), transaction(150, 1_000)).unwrap();
+        let parent = wallet.stuck()[0];
+        let change = wallet.change_of(parent).unwrap();
+        let spendable = wallet.eligible(&wallet.tiers[0]);
+        assert!(spendable.candidates.contains(&change));
+        wallet.spend(&spendable.candidates, &[15_000], Some(14_000), transaction(110, 1_000)).unwrap();
+        assert!(wallet.unbroadcast(parent).is_err());
+    }
+
+    #[test]
+    fn inputs_not_given_to_the_selector_can_not_be_spent() {
+        let mut wallet = wallet(vec![EligibilityFilter::confirmations(1)]);
+        wallet.advance(0, &row(0.0)).unwrap();
+        wallet.receive(10_000);
+
+        let spendable = wallet.eligible(&wallet.tiers[0]);
+        assert!(wallet.spend(&spendable.candidates, &[10_000], None, transaction(150, 1_000)).is_err());
+    }
+}